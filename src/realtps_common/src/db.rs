@@ -1,12 +1,14 @@
 use crate::chain::Chain;
+use crate::window::Window;
 use anyhow::{bail, Result};
 use chrono::{DateTime, Utc};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::{self, File};
 use std::io::{BufReader, BufWriter};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Block {
     pub chain: Chain,
     pub block_number: u64,
@@ -15,18 +17,297 @@ pub struct Block {
     /// empty.
     pub prev_block_number: Option<u64>,
     pub timestamp: u64, // seconds since unix epoch
+    /// The number of transactions sent by users.
     pub num_txs: u64,
     pub hash: String,
     // FIXME this could be None, like prev_block_number
     pub parent_hash: String,
+    /// The gas used by the block's transactions, for chains that meter gas.
+    #[serde(default)]
+    pub gas_used: Option<u64>,
+    /// The operations in the block's transactions, for chains where one
+    /// transaction can carry many, like Stellar.
+    #[serde(default)]
+    pub num_ops: Option<u64>,
+    /// The number of transactions the protocol made for itself, like Solana
+    /// votes, which aren't in `num_txs`. `None` for chains whose
+    /// transactions aren't told apart.
+    #[serde(default)]
+    pub num_system_txs: Option<u64>,
+    /// The number of transactions included in the block that failed, which
+    /// aren't in `num_txs`. `None` for chains whose failed transactions
+    /// aren't told apart.
+    #[serde(default)]
+    pub num_failed_txs: Option<u64>,
+    /// The number of transactions issued by other transactions, like
+    /// Algorand inner transactions, which aren't in `num_txs`. `None` for
+    /// chains without them.
+    #[serde(default)]
+    pub num_inner_txs: Option<u64>,
+    /// The number of atomic groups the block's transactions were sent in,
+    /// like Algorand's, whose members are each counted in `num_txs`. `None`
+    /// for chains without them.
+    #[serde(default)]
+    pub num_tx_groups: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CalculationLog {
     pub calculating_start: DateTime<Utc>,
     pub calculating_end: DateTime<Utc>,
     pub newest_block_timestamp: DateTime<Utc>,
     pub oldest_block_timestamp: DateTime<Utc>,
+    /// The number of seconds the calculation is meant to cover.
+    #[serde(default)]
+    pub expected_window_seconds: u64,
+    /// The number of seconds covered by contiguous blocks, which the TPS is
+    /// calculated over. Less than expected if there are missing blocks, or
+    /// unclassified blocks.
+    #[serde(default)]
+    pub covered_window_seconds: u64,
+    /// The number of blocks counted in the covered window.
+    #[serde(default)]
+    pub num_blocks: u64,
+    /// The number of blocks whose transactions weren't counted, since they
+    /// were stored before the chain's transactions were told apart, and
+    /// count system or failed transactions as user transactions.
+    #[serde(default)]
+    pub num_unclassified_blocks: u64,
+    /// The number of runs of missing blocks in the expected window.
+    /// Blocks are only counted down to the first.
+    #[serde(default)]
+    pub num_gaps: u64,
+    /// The covered window as a percentage of the expected window.
+    /// `None` in logs from before coverage was recorded.
+    #[serde(default)]
+    pub coverage_percent: Option<f64>,
+    /// How the transactions and blocks were spread over the covered window,
+    /// `None` if there were no blocks.
+    #[serde(default)]
+    pub stats: Option<TpsStats>,
+}
+
+/// The spread behind a chain's average TPS.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TpsStats {
+    /// Percentiles of the TPS of each minute.
+    pub tps_p50: f64,
+    pub tps_p90: f64,
+    pub tps_p99: f64,
+    /// Seconds between consecutive blocks.
+    pub block_time_mean: f64,
+    pub block_time_median: f64,
+    pub block_time_std_dev: f64,
+    /// The fraction of blocks without transactions.
+    pub empty_block_ratio: f64,
+}
+
+/// The busiest span of a chain's history, within a calculation window.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PeakTps {
+    /// The length of the span, like "10m".
+    pub interval: Window,
+    pub tps: f64,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// What was calculated for a chain over one window.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WindowCalcs {
+    pub window: Window,
+    /// The average TPS of user transactions over the window.
+    pub tps: f64,
+    /// The average TPS of system transactions over the window.
+    pub system_tps: f64,
+    /// The average TPS of failed transactions over the window.
+    pub failed_tps: f64,
+    /// The average TPS of inner transactions over the window.
+    pub inner_tps: f64,
+    /// The busiest span of each peak interval within the window.
+    pub peaks: Vec<PeakTps>,
+    /// For EVM chains.
+    pub gas_per_second: Option<f64>,
+    /// For Stellar.
+    pub ops_per_second: Option<f64>,
+}
+
+/// A reorg noticed while importing, where previously stored blocks were
+/// replaced by blocks from another fork.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Reorg {
+    pub chain: Chain,
+    /// The lowest replaced block number, the first block after the common
+    /// ancestor of the two forks.
+    pub fork_block_number: u64,
+    /// The number of replaced blocks.
+    pub depth: u64,
+    /// The hash of the replaced block at `fork_block_number`.
+    pub old_hash: String,
+    /// The hash of the new block at `fork_block_number`.
+    pub new_hash: String,
+    /// When the reorg was first noticed, which identifies it as it grows.
+    pub time: DateTime<Utc>,
+}
+
+/// The range an import is in the middle of syncing, so that an interrupted
+/// sync can resume where it stopped.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncCheckpoint {
+    pub chain: Chain,
+    /// The highest known block number when the sync began, which the sync
+    /// works back to.
+    pub highest_known_block_number: u64,
+    /// The live head when the sync began, which becomes the highest known
+    /// block number once the sync completes.
+    pub live_head_block_number: u64,
+    /// The next block to fetch.
+    pub next_block_number: u64,
+    /// The hash the next block must have, `None` for the live head.
+    pub next_block_hash: Option<String>,
+    /// The reorg found so far, which may grow as the sync continues.
+    #[serde(default)]
+    pub reorg: Option<Reorg>,
+}
+
+/// The running transaction count behind a chain's TPS, kept between
+/// calculations so that each one only counts newly imported blocks.
+///
+/// Blocks are counted into per-minute buckets, so that blocks falling out of
+/// the window can be subtracted without loading them again.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TpsAccumulator {
+    /// The layout of the counts. Accumulators from other versions are
+    /// counted again from scratch.
+    #[serde(default)]
+    pub version: u32,
+    pub chain: Chain,
+    /// The length of the window counted, in seconds.
+    pub window_seconds: u64,
+    /// The newest counted block, where the next calculation picks up.
+    pub newest_block: BlockRef,
+    /// The block the window starts at, whose transactions aren't counted.
+    /// Kept while the window isn't full, so that it can be extended back if
+    /// older blocks are imported. `None` once the window is full.
+    pub oldest_block: Option<BlockRef>,
+    /// The time the window starts at, in seconds since unix epoch.
+    pub start_timestamp: u64,
+    /// Whether the newest block told system transactions apart. Older blocks
+    /// that don't are unclassified.
+    #[serde(default)]
+    pub has_system_txs: bool,
+    /// Whether the newest block told failed transactions apart. Older blocks
+    /// that don't are unclassified.
+    #[serde(default)]
+    pub has_failed_txs: bool,
+    /// Counts of the blocks in the window, keyed by the start of their minute.
+    pub buckets: BTreeMap<u64, TpsBucket>,
+    /// The gaps found below `oldest_block`, kept so that they aren't
+    /// searched for again until it moves.
+    #[serde(default)]
+    pub gap_scan: Option<GapScan>,
+}
+
+/// The gaps in the stored blocks between the oldest counted block and the
+/// start of the window.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GapScan {
+    /// The block the search started at.
+    pub oldest_block: BlockRef,
+    /// The lowest known block number at the time, below which missing
+    /// blocks aren't gaps.
+    pub lowest_block_number: u64,
+    /// Newest first.
+    pub gaps: Vec<Gap>,
+}
+
+/// A run of missing blocks.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Gap {
+    /// The highest missing block number.
+    pub missing_block_number: u64,
+    /// The timestamp of the stored block just after the gap.
+    pub timestamp: u64,
+}
+
+/// Identifies a block in a chain that may reorganize.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockRef {
+    pub block_number: u64,
+    pub hash: String,
+    pub timestamp: u64,
+}
+
+impl From<&Block> for BlockRef {
+    fn from(block: &Block) -> BlockRef {
+        BlockRef {
+            block_number: block.block_number,
+            hash: block.hash.clone(),
+            timestamp: block.timestamp,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TpsBucket {
+    pub num_txs: u64,
+    #[serde(default)]
+    pub num_system_txs: u64,
+    #[serde(default)]
+    pub num_failed_txs: u64,
+    #[serde(default)]
+    pub num_inner_txs: u64,
+    pub num_blocks: u64,
+    #[serde(default)]
+    pub num_empty_blocks: u64,
+    #[serde(default)]
+    pub gas_used: u64,
+    #[serde(default)]
+    pub num_ops: u64,
+    /// The number of blocks that reported gas used, since blocks stored
+    /// before it was fetched don't.
+    #[serde(default)]
+    pub num_blocks_with_gas_used: u64,
+    /// The number of blocks that reported operations.
+    #[serde(default)]
+    pub num_blocks_with_ops: u64,
+    /// The number of blocks whose transactions aren't counted, since they
+    /// were stored before the chain's transactions were told apart.
+    #[serde(default)]
+    pub num_unclassified_blocks: u64,
+    /// The seconds since their previous blocks of the unclassified blocks,
+    /// which TPS isn't averaged over.
+    #[serde(default)]
+    pub unclassified_seconds: u64,
+    /// The number of blocks by seconds since their previous block.
+    #[serde(default)]
+    pub block_times: BTreeMap<u64, u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    /// Succeeded, waiting to run again.
+    Sleeping,
+    /// Failed, waiting to retry.
+    Failing,
+    /// Succeeded, with nothing more to do.
+    Finished,
+}
+
+/// What the importer's job scheduler knows about a job.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobStatus {
+    /// The job's name, like "import-solana" or "calculate-solana".
+    pub job: String,
+    /// The chain the job works on.
+    pub chain: Chain,
+    pub state: JobState,
+    pub consecutive_failures: u32,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub next_run: Option<DateTime<Utc>>,
 }
 
 pub trait Db: Send + Sync + 'static {
@@ -36,14 +317,60 @@ pub trait Db: Send + Sync + 'static {
     fn store_highest_block_number(&self, chain: Chain, block_number: u64) -> Result<()>;
     fn load_highest_block_number(&self, chain: Chain) -> Result<Option<u64>>;
 
-    fn store_tps(&self, chain: Chain, tps: f64) -> Result<()>;
-    fn load_tps(&self, chain: Chain) -> Result<Option<f64>>;
+    /// The lowest block number we meant to store, below which a missing
+    /// block is the end of our data rather than a gap.
+    fn store_lowest_block_number(&self, chain: Chain, block_number: u64) -> Result<()>;
+    fn load_lowest_block_number(&self, chain: Chain) -> Result<Option<u64>>;
+
+    /// The highest block of the stored chain the last gap repair checked,
+    /// below which the next repair doesn't need to look.
+    fn store_repair_cursor(&self, chain: Chain, cursor: &BlockRef) -> Result<()>;
+    fn load_repair_cursor(&self, chain: Chain) -> Result<Option<BlockRef>>;
+
+    fn store_sync_checkpoint(&self, checkpoint: &SyncCheckpoint) -> Result<()>;
+    fn load_sync_checkpoint(&self, chain: Chain) -> Result<Option<SyncCheckpoint>>;
+    fn remove_sync_checkpoint(&self, chain: Chain) -> Result<()>;
+
+    fn store_window_calcs(&self, chain: Chain, calcs: &WindowCalcs) -> Result<()>;
+    fn load_window_calcs(&self, chain: Chain, window: Window) -> Result<Option<WindowCalcs>>;
+    /// Returns the windows with stored calculations for the chain, shortest
+    /// first.
+    fn load_calculated_windows(&self, chain: Chain) -> Result<Vec<Window>>;
+
+    fn store_tps_accumulator(&self, accumulator: &TpsAccumulator) -> Result<()>;
+    fn load_tps_accumulator(&self, chain: Chain) -> Result<Option<TpsAccumulator>>;
 
     fn remove_block(&self, chain: Chain, block: u64) -> Result<()>;
 
-    fn store_calculation_log(&self, chain: Chain, log: &CalculationLog) -> Result<()>;
+    /// Stores a fetched block that didn't fit the hash chain being imported,
+    /// apart from the trusted blocks.
+    fn store_quarantined_block(&self, block: Block) -> Result<()>;
+    fn remove_quarantined_blocks_below(&self, chain: Chain, block_number: u64) -> Result<()>;
+
+    /// Stores a block that was replaced by a reorg. Orphans are keyed by hash,
+    /// so none are overwritten.
+    fn store_orphan_block(&self, block: Block) -> Result<()>;
+    fn remove_orphan_blocks_below(&self, chain: Chain, block_number: u64) -> Result<()>;
+
+    /// Stores a reorg, replacing the one first noticed at the same time.
+    fn store_reorg(&self, reorg: &Reorg) -> Result<()>;
+    /// Returns every reorg recorded for the chain, oldest first.
+    fn load_reorgs(&self, chain: Chain) -> Result<Vec<Reorg>>;
+    /// Removes reorgs whose fork is below `block_number`.
+    fn remove_reorgs_below(&self, chain: Chain, block_number: u64) -> Result<()>;
 
-    fn load_calculation_log(&self, chain: Chain) -> Result<Option<CalculationLog>>;
+    fn store_calculation_log(
+        &self,
+        chain: Chain,
+        window: Window,
+        log: &CalculationLog,
+    ) -> Result<()>;
+
+    fn store_job_status(&self, status: &JobStatus) -> Result<()>;
+    /// Returns the status of every job that has run, sorted by job name.
+    fn load_job_statuses(&self) -> Result<Vec<JobStatus>>;
+
+    fn load_calculation_log(&self, chain: Chain, window: Window) -> Result<Option<CalculationLog>>;
 }
 
 pub struct JsonDb;
@@ -51,9 +378,17 @@ pub struct JsonDb;
 pub static JSON_DB_DIR: &str = "db";
 pub static DB_DIR_BLOCKS: &str = "blocks";
 pub static DB_DIR_META: &str = "meta";
+pub static DB_DIR_QUARANTINE: &str = "quarantine";
+pub static DB_DIR_ORPHANS: &str = "orphans";
+pub static DB_DIR_JOBS: &str = "jobs";
 pub static HIGHEST_BLOCK_NUMBER: &str = "highest_block_number";
-pub static TRANSACTIONS_PER_SECOND: &str = "tps";
+pub static LOWEST_BLOCK_NUMBER: &str = "lowest_block_number";
+pub static REPAIR_CURSOR: &str = "repair_cursor";
+pub static WINDOW_CALCS: &str = "window_calcs";
 pub static CALCULATION_LOG: &str = "calculation_log";
+pub static REORGS: &str = "reorgs";
+pub static SYNC_CHECKPOINT: &str = "sync_checkpoint";
+pub static TPS_ACCUMULATOR: &str = "tps_accumulator";
 
 impl Db for JsonDb {
     fn store_block(&self, block: Block) -> Result<()> {
@@ -86,17 +421,107 @@ impl Db for JsonDb {
         read_json_db(&format!("{}", chain), DB_DIR_META, HIGHEST_BLOCK_NUMBER)
     }
 
-    fn store_tps(&self, chain: Chain, tps: f64) -> Result<()> {
+    fn store_lowest_block_number(&self, chain: Chain, block_number: u64) -> Result<()> {
         write_json_db(
             &format!("{}", chain),
             DB_DIR_META,
-            TRANSACTIONS_PER_SECOND,
-            &tps,
+            LOWEST_BLOCK_NUMBER,
+            &block_number,
+        )
+    }
+
+    fn load_lowest_block_number(&self, chain: Chain) -> Result<Option<u64>> {
+        read_json_db(&format!("{}", chain), DB_DIR_META, LOWEST_BLOCK_NUMBER)
+    }
+
+    fn store_repair_cursor(&self, chain: Chain, cursor: &BlockRef) -> Result<()> {
+        write_json_db(&format!("{}", chain), DB_DIR_META, REPAIR_CURSOR, cursor)
+    }
+
+    fn load_repair_cursor(&self, chain: Chain) -> Result<Option<BlockRef>> {
+        read_json_db(&format!("{}", chain), DB_DIR_META, REPAIR_CURSOR)
+    }
+
+    fn store_sync_checkpoint(&self, checkpoint: &SyncCheckpoint) -> Result<()> {
+        write_json_db(
+            &format!("{}", checkpoint.chain),
+            DB_DIR_META,
+            SYNC_CHECKPOINT,
+            checkpoint,
+        )
+    }
+
+    fn load_sync_checkpoint(&self, chain: Chain) -> Result<Option<SyncCheckpoint>> {
+        read_json_db(&format!("{}", chain), DB_DIR_META, SYNC_CHECKPOINT)
+    }
+
+    fn remove_sync_checkpoint(&self, chain: Chain) -> Result<()> {
+        let file_path = format!(
+            "{}/{}/{}/{}",
+            JSON_DB_DIR, chain, DB_DIR_META, SYNC_CHECKPOINT
+        );
+        match fs::remove_file(file_path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            r => Ok(r?),
+        }
+    }
+
+    fn store_window_calcs(&self, chain: Chain, calcs: &WindowCalcs) -> Result<()> {
+        write_json_db(
+            &format!("{}", chain),
+            DB_DIR_META,
+            &window_file(WINDOW_CALCS, calcs.window),
+            calcs,
+        )
+    }
+
+    fn load_window_calcs(&self, chain: Chain, window: Window) -> Result<Option<WindowCalcs>> {
+        read_json_db(
+            &format!("{}", chain),
+            DB_DIR_META,
+            &window_file(WINDOW_CALCS, window),
+        )
+    }
+
+    fn load_calculated_windows(&self, chain: Chain) -> Result<Vec<Window>> {
+        let dir = format!("{}/{}/{}", JSON_DB_DIR, chain, DB_DIR_META);
+        let entries = match fs::read_dir(&dir) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            entries => entries?,
+        };
+
+        let mut windows = vec![];
+        for entry in entries {
+            let file_name = entry?.file_name();
+            let file_name = file_name.to_string_lossy();
+            // Half-written files have an extension, and don't parse
+            let window = if file_name == WINDOW_CALCS {
+                Some(Window::default())
+            } else {
+                file_name
+                    .strip_prefix(WINDOW_CALCS)
+                    .and_then(|window| window.strip_prefix('_'))
+                    .and_then(|window| Window::try_from(window).ok())
+            };
+            windows.extend(window);
+        }
+
+        windows.sort();
+
+        Ok(windows)
+    }
+
+    fn store_tps_accumulator(&self, accumulator: &TpsAccumulator) -> Result<()> {
+        write_json_db(
+            &format!("{}", accumulator.chain),
+            DB_DIR_META,
+            TPS_ACCUMULATOR,
+            accumulator,
         )
     }
 
-    fn load_tps(&self, chain: Chain) -> Result<Option<f64>> {
-        read_json_db(&format!("{}", chain), DB_DIR_META, TRANSACTIONS_PER_SECOND)
+    fn load_tps_accumulator(&self, chain: Chain) -> Result<Option<TpsAccumulator>> {
+        read_json_db(&format!("{}", chain), DB_DIR_META, TPS_ACCUMULATOR)
     }
 
     fn remove_block(&self, chain: Chain, block: u64) -> Result<()> {
@@ -105,12 +530,145 @@ impl Db for JsonDb {
         Ok(())
     }
 
-    fn store_calculation_log(&self, chain: Chain, log: &CalculationLog) -> Result<()> {
-        write_json_db(&format!("{}", chain), DB_DIR_META, CALCULATION_LOG, log)
+    fn store_quarantined_block(&self, block: Block) -> Result<()> {
+        write_json_db(
+            &format!("{}", block.chain),
+            DB_DIR_QUARANTINE,
+            &format!("{}", block.block_number),
+            &block,
+        )
+    }
+
+    fn remove_quarantined_blocks_below(&self, chain: Chain, block_number: u64) -> Result<()> {
+        remove_block_files_below(chain, DB_DIR_QUARANTINE, block_number)
+    }
+
+    fn store_orphan_block(&self, block: Block) -> Result<()> {
+        write_json_db(
+            &format!("{}", block.chain),
+            DB_DIR_ORPHANS,
+            &format!("{}-{}", block.block_number, block.hash),
+            &block,
+        )
+    }
+
+    fn remove_orphan_blocks_below(&self, chain: Chain, block_number: u64) -> Result<()> {
+        remove_block_files_below(chain, DB_DIR_ORPHANS, block_number)
     }
 
-    fn load_calculation_log(&self, chain: Chain) -> Result<Option<CalculationLog>> {
-        read_json_db(&format!("{}", chain), DB_DIR_META, CALCULATION_LOG)
+    fn store_reorg(&self, reorg: &Reorg) -> Result<()> {
+        let mut reorgs = self.load_reorgs(reorg.chain)?;
+        match reorgs
+            .iter_mut()
+            .find(|stored_reorg| stored_reorg.time == reorg.time)
+        {
+            Some(stored_reorg) => *stored_reorg = reorg.clone(),
+            None => reorgs.push(reorg.clone()),
+        }
+        write_json_db(&format!("{}", reorg.chain), DB_DIR_META, REORGS, &reorgs)
+    }
+
+    fn load_reorgs(&self, chain: Chain) -> Result<Vec<Reorg>> {
+        let reorgs = read_json_db(&format!("{}", chain), DB_DIR_META, REORGS)?;
+        Ok(reorgs.unwrap_or_default())
+    }
+
+    fn remove_reorgs_below(&self, chain: Chain, block_number: u64) -> Result<()> {
+        let mut reorgs = self.load_reorgs(chain)?;
+        let num_reorgs = reorgs.len();
+        reorgs.retain(|reorg| reorg.fork_block_number >= block_number);
+        if reorgs.len() == num_reorgs {
+            return Ok(());
+        }
+        write_json_db(&format!("{}", chain), DB_DIR_META, REORGS, &reorgs)
+    }
+
+    fn store_calculation_log(
+        &self,
+        chain: Chain,
+        window: Window,
+        log: &CalculationLog,
+    ) -> Result<()> {
+        write_json_db(
+            &format!("{}", chain),
+            DB_DIR_META,
+            &window_file(CALCULATION_LOG, window),
+            log,
+        )
+    }
+
+    fn load_calculation_log(&self, chain: Chain, window: Window) -> Result<Option<CalculationLog>> {
+        read_json_db(
+            &format!("{}", chain),
+            DB_DIR_META,
+            &window_file(CALCULATION_LOG, window),
+        )
+    }
+
+    fn store_job_status(&self, status: &JobStatus) -> Result<()> {
+        let file_dir = format!("{}/{}", JSON_DB_DIR, DB_DIR_JOBS);
+        write_json_file(&file_dir, &status.job, status)
+    }
+
+    fn load_job_statuses(&self) -> Result<Vec<JobStatus>> {
+        let dir = format!("{}/{}", JSON_DB_DIR, DB_DIR_JOBS);
+        let entries = match fs::read_dir(&dir) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            entries => entries?,
+        };
+
+        let mut statuses = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            // Skip half-written files
+            if path.extension().is_some() {
+                continue;
+            }
+            if let Some(status) = read_json_file(&path.to_string_lossy())? {
+                statuses.push(status);
+            }
+        }
+
+        statuses.sort_by(|a: &JobStatus, b| a.job.cmp(&b.job));
+
+        Ok(statuses)
+    }
+}
+
+/// Removes the files in one of a chain's directories that are named for a
+/// block number below `block_number`, optionally followed by `-` and more.
+fn remove_block_files_below(chain: Chain, dir: &str, block_number: u64) -> Result<()> {
+    let dir = format!("{}/{}/{}", JSON_DB_DIR, chain, dir);
+    let entries = match fs::read_dir(&dir) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        entries => entries?,
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+        // Half-written files don't parse, and are left alone
+        let file_block_number = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| file_name.split('-').next())
+            .and_then(|file_block_number| file_block_number.parse::<u64>().ok());
+        if let Some(file_block_number) = file_block_number {
+            if file_block_number < block_number {
+                fs::remove_file(path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The file for one window's data. The default window keeps the file names
+/// from before there were other windows.
+fn window_file(file: &str, window: Window) -> String {
+    if window == Window::default() {
+        file.to_string()
+    } else {
+        format!("{}_{}", file, window)
     }
 }
 
@@ -119,9 +677,16 @@ where
     T: Serialize + ?Sized,
 {
     let file_dir = format!("{}/{}/{}", JSON_DB_DIR, &chain, &sub_dir);
-    fs::create_dir_all(&file_dir)?;
+    write_json_file(&file_dir, file, data)
+}
 
-    let file_path = format!("{}/{}/{}/{}", JSON_DB_DIR, &chain, &sub_dir, &file);
+fn write_json_file<T>(file_dir: &str, file: &str, data: &T) -> Result<()>
+where
+    T: Serialize + ?Sized,
+{
+    fs::create_dir_all(file_dir)?;
+
+    let file_path = format!("{}/{}", file_dir, file);
     let temp_file_path = format!("{}.{}.temp", &file_path, rand::random::<u32>());
 
     let file = File::create(&temp_file_path)?;
@@ -144,7 +709,13 @@ where
     T: DeserializeOwned,
 {
     let path = format!("{}/{}/{}/{}", JSON_DB_DIR, &chain, &sub_dir, &file);
+    read_json_file(&path)
+}
 
+fn read_json_file<T>(path: &str) -> Result<Option<T>>
+where
+    T: DeserializeOwned,
+{
     let file = File::open(path);
     match file {
         Err(e) => match e.kind() {
@@ -0,0 +1,106 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const SECONDS_PER_MINUTE: u64 = 60;
+const SECONDS_PER_HOUR: u64 = 60 * SECONDS_PER_MINUTE;
+const SECONDS_PER_DAY: u64 = 24 * SECONDS_PER_HOUR;
+
+/// A span of time TPS is calculated over, written like "1h", "24h" or "7d".
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(try_from = "String", into = "String")]
+pub struct Window {
+    seconds: u64,
+}
+
+impl Window {
+    pub const HOUR: Window = Window::from_seconds(SECONDS_PER_HOUR);
+    pub const DAY: Window = Window::from_seconds(SECONDS_PER_DAY);
+    pub const WEEK: Window = Window::from_seconds(7 * SECONDS_PER_DAY);
+    pub const MONTH: Window = Window::from_seconds(30 * SECONDS_PER_DAY);
+
+    pub const fn from_seconds(seconds: u64) -> Window {
+        Window { seconds }
+    }
+
+    pub fn seconds(&self) -> u64 {
+        self.seconds
+    }
+
+    /// The spans peak TPS is found over, shortest first.
+    /// Each is a whole number of minutes.
+    pub fn peak_intervals() -> Vec<Window> {
+        vec![
+            Window::from_seconds(SECONDS_PER_MINUTE),
+            Window::from_seconds(10 * SECONDS_PER_MINUTE),
+            Window::HOUR,
+        ]
+    }
+}
+
+impl Default for Window {
+    /// The window RealTPS has always calculated over.
+    fn default() -> Window {
+        Window::WEEK
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Window {
+    type Error = anyhow::Error;
+
+    fn try_from(window: &'a str) -> Result<Window> {
+        let unit_index = window
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| anyhow!("window `{}` needs a unit, s, m, h or d", window))?;
+        let (count, unit) = window.split_at(unit_index);
+        let count: u64 = count
+            .parse()
+            .map_err(|_| anyhow!("window `{}` needs a number", window))?;
+        let unit_seconds = match unit {
+            "s" => 1,
+            "m" => SECONDS_PER_MINUTE,
+            "h" => SECONDS_PER_HOUR,
+            "d" => SECONDS_PER_DAY,
+            _ => bail!("unknown unit in window `{}`, use s, m, h or d", window),
+        };
+        let seconds = count
+            .checked_mul(unit_seconds)
+            .ok_or_else(|| anyhow!("window `{}` is too long", window))?;
+        if seconds == 0 {
+            bail!("window `{}` is empty", window);
+        }
+
+        Ok(Window::from_seconds(seconds))
+    }
+}
+
+impl TryFrom<String> for Window {
+    type Error = anyhow::Error;
+
+    fn try_from(window: String) -> Result<Window> {
+        Window::try_from(window.as_str())
+    }
+}
+
+impl From<Window> for String {
+    fn from(window: Window) -> String {
+        window.to_string()
+    }
+}
+
+// Used in `JsonDb` paths and URLs.
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.seconds;
+        // Days only past one, so a day reads as "24h"
+        if seconds % SECONDS_PER_DAY == 0 && seconds > SECONDS_PER_DAY {
+            write!(f, "{}d", seconds / SECONDS_PER_DAY)
+        } else if seconds % SECONDS_PER_HOUR == 0 {
+            write!(f, "{}h", seconds / SECONDS_PER_HOUR)
+        } else if seconds % SECONDS_PER_MINUTE == 0 {
+            write!(f, "{}m", seconds / SECONDS_PER_MINUTE)
+        } else {
+            write!(f, "{}s", seconds)
+        }
+    }
+}
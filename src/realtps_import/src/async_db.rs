@@ -0,0 +1,678 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use realtps_common::{
+    chain::Chain,
+    db::{
+        Block, BlockRef, CalculationLog, Db, JobStatus, Reorg, SyncCheckpoint, TpsAccumulator,
+        WindowCalcs,
+    },
+    window::Window,
+};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::task;
+
+/// The number of blocks `BlockWalker` loads from the db at first.
+/// Many walks only need a few blocks, like counting blocks imported since
+/// the last calculation.
+const MIN_WALK_BATCH_SIZE: usize = 8;
+
+/// The most blocks `BlockWalker` loads from the db at once.
+const MAX_WALK_BATCH_SIZE: usize = 1000;
+
+/// The importer's view of the db.
+///
+/// Like `Db`, but async, and with batch operations for the hot paths that
+/// would otherwise make a db round trip per block.
+#[async_trait]
+pub trait AsyncDb: Send + Sync + 'static {
+    async fn store_block(&self, block: Block) -> Result<()>;
+    async fn load_block(&self, chain: Chain, block_number: u64) -> Result<Option<Block>>;
+    async fn remove_blocks(&self, chain: Chain, block_numbers: Vec<u64>) -> Result<()>;
+
+    /// Loads up to `limit` blocks, starting with `block_number` and following
+    /// each block's `prev_block_number` back. Stops early at a block whose
+    /// previous block isn't stored.
+    async fn load_blocks_back(
+        &self,
+        chain: Chain,
+        block_number: u64,
+        limit: usize,
+    ) -> Result<Vec<Block>>;
+
+    async fn store_highest_block_number(&self, chain: Chain, block_number: u64) -> Result<()>;
+    async fn load_highest_block_number(&self, chain: Chain) -> Result<Option<u64>>;
+
+    async fn store_lowest_block_number(&self, chain: Chain, block_number: u64) -> Result<()>;
+    async fn load_lowest_block_number(&self, chain: Chain) -> Result<Option<u64>>;
+
+    async fn store_repair_cursor(&self, chain: Chain, cursor: BlockRef) -> Result<()>;
+    async fn load_repair_cursor(&self, chain: Chain) -> Result<Option<BlockRef>>;
+
+    async fn store_sync_checkpoint(&self, checkpoint: SyncCheckpoint) -> Result<()>;
+    async fn load_sync_checkpoint(&self, chain: Chain) -> Result<Option<SyncCheckpoint>>;
+    async fn remove_sync_checkpoint(&self, chain: Chain) -> Result<()>;
+
+    async fn store_window_calcs(&self, chain: Chain, calcs: WindowCalcs) -> Result<()>;
+    async fn load_window_calcs(&self, chain: Chain, window: Window) -> Result<Option<WindowCalcs>>;
+    async fn load_calculated_windows(&self, chain: Chain) -> Result<Vec<Window>>;
+
+    async fn store_tps_accumulator(&self, accumulator: TpsAccumulator) -> Result<()>;
+    async fn load_tps_accumulator(&self, chain: Chain) -> Result<Option<TpsAccumulator>>;
+
+    async fn store_quarantined_block(&self, block: Block) -> Result<()>;
+    async fn remove_quarantined_blocks_below(&self, chain: Chain, block_number: u64) -> Result<()>;
+    async fn store_orphan_block(&self, block: Block) -> Result<()>;
+    async fn remove_orphan_blocks_below(&self, chain: Chain, block_number: u64) -> Result<()>;
+
+    async fn store_reorg(&self, reorg: Reorg) -> Result<()>;
+    async fn load_reorgs(&self, chain: Chain) -> Result<Vec<Reorg>>;
+    async fn remove_reorgs_below(&self, chain: Chain, block_number: u64) -> Result<()>;
+
+    async fn store_calculation_log(
+        &self,
+        chain: Chain,
+        window: Window,
+        log: CalculationLog,
+    ) -> Result<()>;
+    async fn load_calculation_log(
+        &self,
+        chain: Chain,
+        window: Window,
+    ) -> Result<Option<CalculationLog>>;
+
+    async fn store_job_status(&self, status: JobStatus) -> Result<()>;
+    async fn load_job_statuses(&self) -> Result<Vec<JobStatus>>;
+}
+
+/// Adapts a synchronous `Db`, like `JsonDb`, to `AsyncDb`,
+/// by running each operation on the blocking thread pool.
+pub struct BlockingDb<D> {
+    db: Arc<D>,
+}
+
+impl<D: Db> BlockingDb<D> {
+    pub fn new(db: D) -> BlockingDb<D> {
+        BlockingDb { db: Arc::new(db) }
+    }
+
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&D) -> Result<T> + Send + 'static,
+    {
+        let db = self.db.clone();
+        task::spawn_blocking(move || f(&db)).await?
+    }
+}
+
+#[async_trait]
+impl<D: Db> AsyncDb for BlockingDb<D> {
+    async fn store_block(&self, block: Block) -> Result<()> {
+        self.run(move |db| db.store_block(block)).await
+    }
+
+    async fn load_block(&self, chain: Chain, block_number: u64) -> Result<Option<Block>> {
+        self.run(move |db| db.load_block(chain, block_number)).await
+    }
+
+    async fn remove_blocks(&self, chain: Chain, block_numbers: Vec<u64>) -> Result<()> {
+        self.run(move |db| {
+            for block_number in block_numbers {
+                db.remove_block(chain, block_number)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn load_blocks_back(
+        &self,
+        chain: Chain,
+        block_number: u64,
+        limit: usize,
+    ) -> Result<Vec<Block>> {
+        self.run(move |db| {
+            let mut blocks = vec![];
+            let mut next_block_number = Some(block_number);
+            while let Some(block_number) = next_block_number {
+                if blocks.len() == limit {
+                    break;
+                }
+                match db.load_block(chain, block_number)? {
+                    Some(block) => {
+                        next_block_number = block.prev_block_number;
+                        blocks.push(block);
+                    }
+                    None => break,
+                }
+            }
+            Ok(blocks)
+        })
+        .await
+    }
+
+    async fn store_highest_block_number(&self, chain: Chain, block_number: u64) -> Result<()> {
+        self.run(move |db| db.store_highest_block_number(chain, block_number))
+            .await
+    }
+
+    async fn load_highest_block_number(&self, chain: Chain) -> Result<Option<u64>> {
+        self.run(move |db| db.load_highest_block_number(chain))
+            .await
+    }
+
+    async fn store_lowest_block_number(&self, chain: Chain, block_number: u64) -> Result<()> {
+        self.run(move |db| db.store_lowest_block_number(chain, block_number))
+            .await
+    }
+
+    async fn load_lowest_block_number(&self, chain: Chain) -> Result<Option<u64>> {
+        self.run(move |db| db.load_lowest_block_number(chain)).await
+    }
+
+    async fn store_repair_cursor(&self, chain: Chain, cursor: BlockRef) -> Result<()> {
+        self.run(move |db| db.store_repair_cursor(chain, &cursor))
+            .await
+    }
+
+    async fn load_repair_cursor(&self, chain: Chain) -> Result<Option<BlockRef>> {
+        self.run(move |db| db.load_repair_cursor(chain)).await
+    }
+
+    async fn store_sync_checkpoint(&self, checkpoint: SyncCheckpoint) -> Result<()> {
+        self.run(move |db| db.store_sync_checkpoint(&checkpoint))
+            .await
+    }
+
+    async fn load_sync_checkpoint(&self, chain: Chain) -> Result<Option<SyncCheckpoint>> {
+        self.run(move |db| db.load_sync_checkpoint(chain)).await
+    }
+
+    async fn remove_sync_checkpoint(&self, chain: Chain) -> Result<()> {
+        self.run(move |db| db.remove_sync_checkpoint(chain)).await
+    }
+
+    async fn store_window_calcs(&self, chain: Chain, calcs: WindowCalcs) -> Result<()> {
+        self.run(move |db| db.store_window_calcs(chain, &calcs))
+            .await
+    }
+
+    async fn load_window_calcs(&self, chain: Chain, window: Window) -> Result<Option<WindowCalcs>> {
+        self.run(move |db| db.load_window_calcs(chain, window))
+            .await
+    }
+
+    async fn load_calculated_windows(&self, chain: Chain) -> Result<Vec<Window>> {
+        self.run(move |db| db.load_calculated_windows(chain)).await
+    }
+
+    async fn store_tps_accumulator(&self, accumulator: TpsAccumulator) -> Result<()> {
+        self.run(move |db| db.store_tps_accumulator(&accumulator))
+            .await
+    }
+
+    async fn load_tps_accumulator(&self, chain: Chain) -> Result<Option<TpsAccumulator>> {
+        self.run(move |db| db.load_tps_accumulator(chain)).await
+    }
+
+    async fn store_quarantined_block(&self, block: Block) -> Result<()> {
+        self.run(move |db| db.store_quarantined_block(block)).await
+    }
+
+    async fn remove_quarantined_blocks_below(&self, chain: Chain, block_number: u64) -> Result<()> {
+        self.run(move |db| db.remove_quarantined_blocks_below(chain, block_number))
+            .await
+    }
+
+    async fn store_orphan_block(&self, block: Block) -> Result<()> {
+        self.run(move |db| db.store_orphan_block(block)).await
+    }
+
+    async fn remove_orphan_blocks_below(&self, chain: Chain, block_number: u64) -> Result<()> {
+        self.run(move |db| db.remove_orphan_blocks_below(chain, block_number))
+            .await
+    }
+
+    async fn store_reorg(&self, reorg: Reorg) -> Result<()> {
+        self.run(move |db| db.store_reorg(&reorg)).await
+    }
+
+    async fn load_reorgs(&self, chain: Chain) -> Result<Vec<Reorg>> {
+        self.run(move |db| db.load_reorgs(chain)).await
+    }
+
+    async fn remove_reorgs_below(&self, chain: Chain, block_number: u64) -> Result<()> {
+        self.run(move |db| db.remove_reorgs_below(chain, block_number))
+            .await
+    }
+
+    async fn store_calculation_log(
+        &self,
+        chain: Chain,
+        window: Window,
+        log: CalculationLog,
+    ) -> Result<()> {
+        self.run(move |db| db.store_calculation_log(chain, window, &log))
+            .await
+    }
+
+    async fn load_calculation_log(
+        &self,
+        chain: Chain,
+        window: Window,
+    ) -> Result<Option<CalculationLog>> {
+        self.run(move |db| db.load_calculation_log(chain, window))
+            .await
+    }
+
+    async fn store_job_status(&self, status: JobStatus) -> Result<()> {
+        self.run(move |db| db.store_job_status(&status)).await
+    }
+
+    async fn load_job_statuses(&self) -> Result<Vec<JobStatus>> {
+        self.run(move |db| db.load_job_statuses()).await
+    }
+}
+
+/// Walks stored blocks from a block back through their previous blocks,
+/// loading them from the db in batches.
+pub struct BlockWalker<'db> {
+    db: &'db dyn AsyncDb,
+    chain: Chain,
+    next_block_number: Option<u64>,
+    blocks: VecDeque<Block>,
+    /// Doubles with each batch, up to `MAX_WALK_BATCH_SIZE`.
+    batch_size: usize,
+}
+
+impl<'db> BlockWalker<'db> {
+    pub fn new(db: &'db dyn AsyncDb, chain: Chain, block_number: u64) -> BlockWalker<'db> {
+        BlockWalker {
+            db,
+            chain,
+            next_block_number: Some(block_number),
+            blocks: VecDeque::new(),
+            batch_size: MIN_WALK_BATCH_SIZE,
+        }
+    }
+
+    /// Returns the next block back, or `None` once a block's previous block
+    /// isn't stored.
+    pub async fn next(&mut self) -> Result<Option<Block>> {
+        if self.blocks.is_empty() {
+            if let Some(block_number) = self.next_block_number {
+                let blocks = self
+                    .db
+                    .load_blocks_back(self.chain, block_number, self.batch_size)
+                    .await?;
+                // A short batch means the walk hit a missing block
+                self.next_block_number = if blocks.len() == self.batch_size {
+                    blocks.last().and_then(|block| block.prev_block_number)
+                } else {
+                    None
+                };
+                self.blocks.extend(blocks);
+                self.batch_size = (self.batch_size * 2).min(MAX_WALK_BATCH_SIZE);
+            }
+        }
+
+        Ok(self.blocks.pop_front())
+    }
+}
+
+#[cfg(test)]
+pub mod test_async_db {
+    use super::{AsyncDb, BlockWalker};
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use realtps_common::{
+        chain::Chain,
+        db::{
+            Block, BlockRef, CalculationLog, JobStatus, Reorg, SyncCheckpoint, TpsAccumulator,
+            WindowCalcs,
+        },
+        window::Window,
+    };
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// An `AsyncDb` in memory, for tests.
+    #[derive(Default)]
+    pub struct MemoryDb {
+        blocks: Mutex<HashMap<(Chain, u64), Block>>,
+        highest_block_numbers: Mutex<HashMap<Chain, u64>>,
+        lowest_block_numbers: Mutex<HashMap<Chain, u64>>,
+        repair_cursors: Mutex<HashMap<Chain, BlockRef>>,
+        sync_checkpoints: Mutex<HashMap<Chain, SyncCheckpoint>>,
+        window_calcs: Mutex<HashMap<(Chain, Window), WindowCalcs>>,
+        tps_accumulators: Mutex<HashMap<Chain, TpsAccumulator>>,
+        quarantined_blocks: Mutex<Vec<Block>>,
+        orphan_blocks: Mutex<Vec<Block>>,
+        reorgs: Mutex<Vec<Reorg>>,
+        calculation_logs: Mutex<HashMap<(Chain, Window), CalculationLog>>,
+        job_statuses: Mutex<HashMap<String, JobStatus>>,
+        /// The number of blocks loaded, to check batching.
+        pub num_blocks_loaded: Mutex<usize>,
+    }
+
+    impl MemoryDb {
+        /// Stores a chain of blocks numbered from `first_block_number`, one
+        /// per `timestamps`, with a transaction in each.
+        pub fn with_blocks(chain: Chain, first_block_number: u64, timestamps: &[u64]) -> MemoryDb {
+            let db = MemoryDb::default();
+            for (block_number, timestamp) in (first_block_number..).zip(timestamps) {
+                db.put_block(block(chain, block_number, *timestamp, 1, ""));
+            }
+            if let Some(num_blocks) = u64::try_from(timestamps.len()).ok().filter(|n| *n > 0) {
+                let highest_block_number = first_block_number + num_blocks - 1;
+                db.highest_block_numbers
+                    .lock()
+                    .expect("poisoned")
+                    .insert(chain, highest_block_number);
+            }
+            db
+        }
+
+        pub fn put_block(&self, block: Block) {
+            let mut blocks = self.blocks.lock().expect("poisoned");
+            blocks.insert((block.chain, block.block_number), block);
+        }
+
+        pub fn set_highest_block_number(&self, chain: Chain, block_number: u64) {
+            let mut highest_block_numbers = self.highest_block_numbers.lock().expect("poisoned");
+            highest_block_numbers.insert(chain, block_number);
+        }
+
+        pub fn quarantined_blocks(&self) -> Vec<Block> {
+            self.quarantined_blocks.lock().expect("poisoned").clone()
+        }
+    }
+
+    /// A block whose hash is its number followed by `fork`, so that blocks
+    /// from different forks can be told apart.
+    pub fn block(
+        chain: Chain,
+        block_number: u64,
+        timestamp: u64,
+        num_txs: u64,
+        fork: &str,
+    ) -> Block {
+        Block {
+            chain,
+            block_number,
+            prev_block_number: block_number.checked_sub(1),
+            timestamp,
+            num_txs,
+            hash: format!("{}{}", block_number, fork),
+            parent_hash: format!("{}{}", block_number.saturating_sub(1), fork),
+            gas_used: None,
+            num_ops: None,
+            num_system_txs: None,
+            num_failed_txs: None,
+            num_inner_txs: None,
+            num_tx_groups: None,
+        }
+    }
+
+    #[async_trait]
+    impl AsyncDb for MemoryDb {
+        async fn store_block(&self, block: Block) -> Result<()> {
+            self.put_block(block);
+            Ok(())
+        }
+
+        async fn load_block(&self, chain: Chain, block_number: u64) -> Result<Option<Block>> {
+            let blocks = self.blocks.lock().expect("poisoned");
+            let block = blocks.get(&(chain, block_number)).cloned();
+            if block.is_some() {
+                *self.num_blocks_loaded.lock().expect("poisoned") += 1;
+            }
+            Ok(block)
+        }
+
+        async fn remove_blocks(&self, chain: Chain, block_numbers: Vec<u64>) -> Result<()> {
+            let mut blocks = self.blocks.lock().expect("poisoned");
+            for block_number in block_numbers {
+                blocks.remove(&(chain, block_number));
+            }
+            Ok(())
+        }
+
+        async fn load_blocks_back(
+            &self,
+            chain: Chain,
+            block_number: u64,
+            limit: usize,
+        ) -> Result<Vec<Block>> {
+            let mut blocks = vec![];
+            let mut next_block_number = Some(block_number);
+            while let Some(block_number) = next_block_number {
+                if blocks.len() == limit {
+                    break;
+                }
+                match self.load_block(chain, block_number).await? {
+                    Some(block) => {
+                        next_block_number = block.prev_block_number;
+                        blocks.push(block);
+                    }
+                    None => break,
+                }
+            }
+            Ok(blocks)
+        }
+
+        async fn store_highest_block_number(&self, chain: Chain, block_number: u64) -> Result<()> {
+            self.set_highest_block_number(chain, block_number);
+            Ok(())
+        }
+
+        async fn load_highest_block_number(&self, chain: Chain) -> Result<Option<u64>> {
+            let highest_block_numbers = self.highest_block_numbers.lock().expect("poisoned");
+            Ok(highest_block_numbers.get(&chain).cloned())
+        }
+
+        async fn store_lowest_block_number(&self, chain: Chain, block_number: u64) -> Result<()> {
+            let mut lowest_block_numbers = self.lowest_block_numbers.lock().expect("poisoned");
+            lowest_block_numbers.insert(chain, block_number);
+            Ok(())
+        }
+
+        async fn load_lowest_block_number(&self, chain: Chain) -> Result<Option<u64>> {
+            let lowest_block_numbers = self.lowest_block_numbers.lock().expect("poisoned");
+            Ok(lowest_block_numbers.get(&chain).cloned())
+        }
+
+        async fn store_repair_cursor(&self, chain: Chain, cursor: BlockRef) -> Result<()> {
+            let mut repair_cursors = self.repair_cursors.lock().expect("poisoned");
+            repair_cursors.insert(chain, cursor);
+            Ok(())
+        }
+
+        async fn load_repair_cursor(&self, chain: Chain) -> Result<Option<BlockRef>> {
+            let repair_cursors = self.repair_cursors.lock().expect("poisoned");
+            Ok(repair_cursors.get(&chain).cloned())
+        }
+
+        async fn store_sync_checkpoint(&self, checkpoint: SyncCheckpoint) -> Result<()> {
+            let mut sync_checkpoints = self.sync_checkpoints.lock().expect("poisoned");
+            sync_checkpoints.insert(checkpoint.chain, checkpoint);
+            Ok(())
+        }
+
+        async fn load_sync_checkpoint(&self, chain: Chain) -> Result<Option<SyncCheckpoint>> {
+            let sync_checkpoints = self.sync_checkpoints.lock().expect("poisoned");
+            Ok(sync_checkpoints.get(&chain).cloned())
+        }
+
+        async fn remove_sync_checkpoint(&self, chain: Chain) -> Result<()> {
+            let mut sync_checkpoints = self.sync_checkpoints.lock().expect("poisoned");
+            sync_checkpoints.remove(&chain);
+            Ok(())
+        }
+
+        async fn store_window_calcs(&self, chain: Chain, calcs: WindowCalcs) -> Result<()> {
+            let mut window_calcs = self.window_calcs.lock().expect("poisoned");
+            window_calcs.insert((chain, calcs.window), calcs);
+            Ok(())
+        }
+
+        async fn load_window_calcs(
+            &self,
+            chain: Chain,
+            window: Window,
+        ) -> Result<Option<WindowCalcs>> {
+            let window_calcs = self.window_calcs.lock().expect("poisoned");
+            Ok(window_calcs.get(&(chain, window)).cloned())
+        }
+
+        async fn load_calculated_windows(&self, chain: Chain) -> Result<Vec<Window>> {
+            let window_calcs = self.window_calcs.lock().expect("poisoned");
+            let mut windows: Vec<Window> = window_calcs
+                .keys()
+                .filter(|(calcs_chain, _)| *calcs_chain == chain)
+                .map(|(_, window)| *window)
+                .collect();
+            windows.sort();
+            Ok(windows)
+        }
+
+        async fn store_tps_accumulator(&self, accumulator: TpsAccumulator) -> Result<()> {
+            let mut tps_accumulators = self.tps_accumulators.lock().expect("poisoned");
+            tps_accumulators.insert(accumulator.chain, accumulator);
+            Ok(())
+        }
+
+        async fn load_tps_accumulator(&self, chain: Chain) -> Result<Option<TpsAccumulator>> {
+            let tps_accumulators = self.tps_accumulators.lock().expect("poisoned");
+            Ok(tps_accumulators.get(&chain).cloned())
+        }
+
+        async fn store_quarantined_block(&self, block: Block) -> Result<()> {
+            self.quarantined_blocks
+                .lock()
+                .expect("poisoned")
+                .push(block);
+            Ok(())
+        }
+
+        async fn remove_quarantined_blocks_below(
+            &self,
+            chain: Chain,
+            block_number: u64,
+        ) -> Result<()> {
+            self.quarantined_blocks
+                .lock()
+                .expect("poisoned")
+                .retain(|block| block.chain != chain || block.block_number >= block_number);
+            Ok(())
+        }
+
+        async fn store_orphan_block(&self, block: Block) -> Result<()> {
+            self.orphan_blocks.lock().expect("poisoned").push(block);
+            Ok(())
+        }
+
+        async fn remove_orphan_blocks_below(&self, chain: Chain, block_number: u64) -> Result<()> {
+            self.orphan_blocks
+                .lock()
+                .expect("poisoned")
+                .retain(|block| block.chain != chain || block.block_number >= block_number);
+            Ok(())
+        }
+
+        async fn store_reorg(&self, reorg: Reorg) -> Result<()> {
+            let mut reorgs = self.reorgs.lock().expect("poisoned");
+            match reorgs.iter_mut().find(|stored_reorg| {
+                stored_reorg.chain == reorg.chain && stored_reorg.time == reorg.time
+            }) {
+                Some(stored_reorg) => *stored_reorg = reorg,
+                None => reorgs.push(reorg),
+            }
+            Ok(())
+        }
+
+        async fn load_reorgs(&self, chain: Chain) -> Result<Vec<Reorg>> {
+            let reorgs = self.reorgs.lock().expect("poisoned");
+            Ok(reorgs
+                .iter()
+                .filter(|reorg| reorg.chain == chain)
+                .cloned()
+                .collect())
+        }
+
+        async fn remove_reorgs_below(&self, chain: Chain, block_number: u64) -> Result<()> {
+            self.reorgs
+                .lock()
+                .expect("poisoned")
+                .retain(|reorg| reorg.chain != chain || reorg.fork_block_number >= block_number);
+            Ok(())
+        }
+
+        async fn store_calculation_log(
+            &self,
+            chain: Chain,
+            window: Window,
+            log: CalculationLog,
+        ) -> Result<()> {
+            let mut calculation_logs = self.calculation_logs.lock().expect("poisoned");
+            calculation_logs.insert((chain, window), log);
+            Ok(())
+        }
+
+        async fn load_calculation_log(
+            &self,
+            chain: Chain,
+            window: Window,
+        ) -> Result<Option<CalculationLog>> {
+            let calculation_logs = self.calculation_logs.lock().expect("poisoned");
+            Ok(calculation_logs.get(&(chain, window)).cloned())
+        }
+
+        async fn store_job_status(&self, status: JobStatus) -> Result<()> {
+            let mut job_statuses = self.job_statuses.lock().expect("poisoned");
+            job_statuses.insert(status.job.clone(), status);
+            Ok(())
+        }
+
+        async fn load_job_statuses(&self) -> Result<Vec<JobStatus>> {
+            let job_statuses = self.job_statuses.lock().expect("poisoned");
+            Ok(job_statuses.values().cloned().collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn walk_blocks() -> Result<()> {
+        let db = MemoryDb::with_blocks(Chain::Ethereum, 0, &[0; 3000]);
+        let mut walker = BlockWalker::new(&db, Chain::Ethereum, 2999);
+        let mut block_numbers = vec![];
+        while let Some(block) = walker.next().await? {
+            block_numbers.push(block.block_number);
+        }
+        assert_eq!(block_numbers, (0..3000).rev().collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn walk_stops_at_missing_block() -> Result<()> {
+        let db = MemoryDb::with_blocks(Chain::Ethereum, 0, &[0; 100]);
+        db.remove_blocks(Chain::Ethereum, vec![50]).await?;
+        let mut walker = BlockWalker::new(&db, Chain::Ethereum, 99);
+        let mut num_blocks = 0;
+        while walker.next().await?.is_some() {
+            num_blocks += 1;
+        }
+        assert_eq!(num_blocks, 49);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn short_walk_loads_few_blocks() -> Result<()> {
+        let db = MemoryDb::with_blocks(Chain::Ethereum, 0, &[0; 3000]);
+        let mut walker = BlockWalker::new(&db, Chain::Ethereum, 2999);
+        walker.next().await?;
+        walker.next().await?;
+        assert!(*db.num_blocks_loaded.lock().expect("poisoned") < 10);
+        Ok(())
+    }
+}
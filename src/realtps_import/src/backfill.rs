@@ -0,0 +1,337 @@
+use crate::async_db::{AsyncDb, BlockWalker};
+use crate::client::Client;
+use crate::helpers::*;
+use crate::pace_setter::PaceSetter;
+use crate::shutdown::Shutdown;
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use log::{debug, info};
+use realtps_common::{chain::Chain, db::Block, window::Window};
+use std::sync::Arc;
+
+/// How far back a backfill imports blocks.
+#[derive(Debug, Copy, Clone)]
+pub enum BackfillTarget {
+    /// Seconds since unix epoch
+    Timestamp(u64),
+    BlockNumber(u64),
+}
+
+impl BackfillTarget {
+    fn is_reached_by(&self, block: &Block) -> bool {
+        match *self {
+            BackfillTarget::Timestamp(timestamp) => block.timestamp <= timestamp,
+            BackfillTarget::BlockNumber(block_number) => block.block_number <= block_number,
+        }
+    }
+}
+
+/// Parses a duration, like `7d`, `12h` or `30m`. Durations are written like
+/// calculation windows.
+pub fn parse_since(since: &str) -> Result<Window> {
+    Window::try_from(since)
+}
+
+impl BackfillTarget {
+    /// The time `since` before now.
+    pub fn since(since: Window) -> Result<BackfillTarget> {
+        let now = u64::try_from(Utc::now().timestamp())?;
+        let timestamp = now.saturating_sub(since.seconds());
+
+        Ok(BackfillTarget::Timestamp(timestamp))
+    }
+}
+
+/// Fetches and stores blocks older than the oldest block connected to the
+/// highest known block, until reaching the target.
+///
+/// Blocks older than `retention` before the highest known block would only
+/// be removed again, so the backfill stops there even short of the target.
+///
+/// This only ever extends the stored chain downward, so it can run beside the
+/// live import, and resumes from wherever it stopped if interrupted. When
+/// shutdown is requested it stops after the block in progress.
+pub async fn backfill(
+    chain: Chain,
+    client: &dyn Client,
+    db: &Arc<dyn AsyncDb>,
+    target: BackfillTarget,
+    block_pace: u64,
+    retention: Window,
+    shutdown: &Shutdown,
+) -> Result<()> {
+    info!("beginning backfill for chain {} to {:?}", chain, target);
+
+    let highest_block_number = load_highest_known_block_number(chain, db).await?;
+    let highest_block_number = highest_block_number
+        .ok_or_else(|| anyhow!("no data for chain {}, import before backfilling", chain))?;
+    let mut blocks = BlockWalker::new(db.as_ref(), chain, highest_block_number);
+    let highest_block = blocks
+        .next()
+        .await?
+        .ok_or_else(|| anyhow!("highest known block missing for chain {}", chain))?;
+
+    let min_timestamp = highest_block.timestamp.saturating_sub(retention.seconds());
+
+    let mut block = load_oldest_connected_block(highest_block, blocks).await?;
+
+    info!(
+        "backfilling chain {} from block {}",
+        chain, block.block_number
+    );
+
+    let mut pace = PaceSetter::with_block_pace(chain, block_pace);
+    let mut num_blocks: u64 = 0;
+
+    loop {
+        if target.is_reached_by(&block) {
+            break;
+        }
+
+        if shutdown.is_requested() {
+            info!(
+                "stopping backfill of chain {} for shutdown at block {}",
+                chain, block.block_number
+            );
+            break;
+        }
+
+        let prev_block_number = match block.prev_block_number {
+            Some(prev_block_number) => prev_block_number,
+            None => {
+                info!("backfill of chain {} reached genesis", chain);
+                break;
+            }
+        };
+
+        let prev_block = fetch_block(chain, client, prev_block_number).await?;
+
+        if prev_block.hash != block.parent_hash {
+            bail!(
+                "backfilled block {} for chain {} doesn't fit the hash chain; expected hash: {}; fetched hash: {}",
+                prev_block_number,
+                chain,
+                block.parent_hash,
+                prev_block.hash
+            );
+        }
+
+        if prev_block.timestamp < min_timestamp {
+            info!(
+                "backfill of chain {} reached the retention window at block {}",
+                chain, block.block_number
+            );
+            break;
+        }
+
+        debug!("backfilled block {} for chain {}", prev_block_number, chain);
+
+        block = prev_block;
+        db.store_block(block.clone()).await?;
+        num_blocks += 1;
+
+        pace.wait().await;
+    }
+
+    let lowest_known_block_number = load_lowest_known_block_number(chain, db).await?;
+    let is_new_lowest = match lowest_known_block_number {
+        Some(lowest_known_block_number) => block.block_number < lowest_known_block_number,
+        None => true,
+    };
+    if is_new_lowest {
+        store_lowest_known_block_number(chain, db, block.block_number).await?;
+    }
+
+    info!(
+        "backfilled {} blocks for chain {} to block {}",
+        num_blocks, chain, block.block_number
+    );
+
+    Ok(())
+}
+
+/// Follows parent links down from the highest known block while the parent
+/// is stored and its hash matches. `blocks` walks on below it.
+async fn load_oldest_connected_block(
+    highest_block: Block,
+    mut blocks: BlockWalker<'_>,
+) -> Result<Block> {
+    let mut block = highest_block;
+
+    while let Some(prev_block) = blocks.next().await? {
+        if prev_block.hash != block.parent_hash {
+            break;
+        }
+        block = prev_block;
+    }
+
+    Ok(block)
+}
+
+#[cfg(test)]
+mod test_backfill {
+    use super::{backfill, BackfillTarget};
+    use crate::async_db::test_async_db::{block, MemoryDb};
+    use crate::async_db::AsyncDb;
+    use crate::client::test_client::FakeClient;
+    use crate::shutdown::Shutdown;
+    use anyhow::Result;
+    use realtps_common::{chain::Chain, window::Window};
+    use std::sync::Arc;
+
+    const CHAIN: Chain = Chain::Ethereum;
+    const RETENTION: Window = Window::from_seconds(1000);
+
+    /// Stores blocks 50 to 60, timestamped with their numbers.
+    fn imported_db() -> Arc<MemoryDb> {
+        let timestamps: Vec<u64> = (50..=60).collect();
+        Arc::new(MemoryDb::with_blocks(CHAIN, 50, &timestamps))
+    }
+
+    async fn stored(db: &MemoryDb, block_number: u64) -> Result<bool> {
+        Ok(db.load_block(CHAIN, block_number).await?.is_some())
+    }
+
+    #[tokio::test]
+    async fn backfill_to_block() -> Result<()> {
+        let db = imported_db();
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        let client = FakeClient::new(CHAIN, 60, vec![]);
+
+        backfill(
+            CHAIN,
+            &client,
+            &async_db,
+            BackfillTarget::BlockNumber(40),
+            0,
+            RETENTION,
+            &Shutdown::default(),
+        )
+        .await?;
+
+        assert!(stored(&db, 40).await? && stored(&db, 49).await?);
+        assert!(!stored(&db, 39).await?);
+        assert_eq!(db.load_lowest_block_number(CHAIN).await?, Some(40));
+        assert_eq!(*client.num_blocks_fetched.lock().expect("poisoned"), 10);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn backfill_to_timestamp() -> Result<()> {
+        let db = imported_db();
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        let client = FakeClient::new(CHAIN, 60, vec![]);
+
+        backfill(
+            CHAIN,
+            &client,
+            &async_db,
+            BackfillTarget::Timestamp(45),
+            0,
+            RETENTION,
+            &Shutdown::default(),
+        )
+        .await?;
+
+        assert!(stored(&db, 45).await?);
+        assert!(!stored(&db, 44).await?);
+        assert_eq!(db.load_lowest_block_number(CHAIN).await?, Some(45));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn backfill_from_oldest_connected_block() -> Result<()> {
+        let db = imported_db();
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        // Disconnected from the blocks above it
+        db.put_block(block(CHAIN, 48, 48, 1, "x"));
+        let client = FakeClient::new(CHAIN, 60, vec![]);
+
+        backfill(
+            CHAIN,
+            &client,
+            &async_db,
+            BackfillTarget::BlockNumber(45),
+            0,
+            RETENTION,
+            &Shutdown::default(),
+        )
+        .await?;
+
+        let block_48 = db.load_block(CHAIN, 48).await?.expect("block");
+        assert_eq!(block_48.hash, "48");
+        assert_eq!(*client.num_blocks_fetched.lock().expect("poisoned"), 5);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn backfill_stops_at_retention() -> Result<()> {
+        let db = imported_db();
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        let client = FakeClient::new(CHAIN, 60, vec![]);
+
+        // Blocks before 45 would be removed again
+        backfill(
+            CHAIN,
+            &client,
+            &async_db,
+            BackfillTarget::BlockNumber(40),
+            0,
+            Window::from_seconds(15),
+            &Shutdown::default(),
+        )
+        .await?;
+
+        assert!(stored(&db, 45).await?);
+        assert!(!stored(&db, 44).await?);
+        assert_eq!(db.load_lowest_block_number(CHAIN).await?, Some(45));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn backfill_rejects_block_off_the_hash_chain() -> Result<()> {
+        let db = imported_db();
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        let client = FakeClient::new(CHAIN, 60, vec![block(CHAIN, 49, 49, 1, "x")]);
+
+        let result = backfill(
+            CHAIN,
+            &client,
+            &async_db,
+            BackfillTarget::BlockNumber(40),
+            0,
+            RETENTION,
+            &Shutdown::default(),
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert!(!stored(&db, 49).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn backfill_stops_for_shutdown() -> Result<()> {
+        let db = imported_db();
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        let shutdown = Shutdown::default();
+        let mut client = FakeClient::new(CHAIN, 60, vec![]);
+        client.shutdown_at = Some((45, shutdown.clone()));
+
+        backfill(
+            CHAIN,
+            &client,
+            &async_db,
+            BackfillTarget::BlockNumber(40),
+            0,
+            RETENTION,
+            &shutdown,
+        )
+        .await?;
+
+        assert!(stored(&db, 45).await?);
+        assert!(!stored(&db, 44).await?);
+        assert_eq!(db.load_lowest_block_number(CHAIN).await?, Some(45));
+        Ok(())
+    }
+}
@@ -1,108 +1,1153 @@
+use crate::async_db::{AsyncDb, BlockWalker};
 use crate::helpers::*;
-use anyhow::{anyhow, Result};
-use chrono::{TimeZone, Utc};
-use realtps_common::{chain::Chain, db::CalculationLog, db::Db};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use log::{debug, info};
+use realtps_common::{
+    chain::{Chain, ChainType},
+    db::{
+        Block, BlockRef, CalculationLog, Gap, GapScan, PeakTps, TpsAccumulator, TpsStats,
+        WindowCalcs,
+    },
+    window::Window,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
+const SECONDS_PER_BUCKET: u64 = 60;
+
+/// Missing runs longer than this many block numbers are taken as the end of
+/// the stored blocks when looking for gaps.
+const MAX_GAP_BLOCKS: u64 = 10_000;
+
+/// The current `TpsAccumulator::version`. Bumped when buckets count
+/// something new, so that it is counted over the whole window.
+const ACCUMULATOR_VERSION: u32 = 7;
+
+/// Calculation settings, from the `[calculate]` section of the RPC
+/// configuration.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CalculateConfig {
+    /// The windows to calculate TPS over. Blocks are kept for the longest.
+    pub windows: Vec<Window>,
+}
+
+impl Default for CalculateConfig {
+    fn default() -> CalculateConfig {
+        CalculateConfig {
+            windows: vec![Window::default()],
+        }
+    }
+}
+
+impl CalculateConfig {
+    pub fn longest_window(&self) -> Window {
+        self.windows.iter().max().cloned().unwrap_or_default()
+    }
+}
+
 pub struct ChainCalcs {
     pub chain: Chain,
-    pub tps: f64,
+    pub windows: Vec<WindowCalcs>,
 }
 
-pub async fn calculate_for_chain(chain: Chain, db: Arc<dyn Db>) -> Result<ChainCalcs> {
+pub async fn calculate_for_chain(
+    chain: Chain,
+    db: Arc<dyn AsyncDb>,
+    config: CalculateConfig,
+) -> Result<ChainCalcs> {
     let calculating_start = Utc::now();
 
     let highest_block_number = load_highest_known_block_number(chain, &db).await?;
     let highest_block_number =
         highest_block_number.ok_or_else(|| anyhow!("no data for chain {}", chain))?;
 
-    let load_block = |number| load_block(chain, &db, number);
-
-    let latest_timestamp = load_block(highest_block_number)
-        .await?
-        .expect("first block")
-        .timestamp;
+    // Count the longest window, which the others are part of
+    let window_seconds = config.longest_window().seconds();
 
-    let seconds_per_week = 60 * 60 * 24 * 7;
-    let min_timestamp = latest_timestamp
-        .checked_sub(seconds_per_week)
-        .expect("underflow");
-
-    let mut current_block = load_block(highest_block_number)
-        .await?
-        .expect("first_block");
+    let accumulator = match db.load_tps_accumulator(chain).await? {
+        Some(accumulator)
+            if accumulator.version == ACCUMULATOR_VERSION
+                && accumulator.window_seconds == window_seconds =>
+        {
+            update_accumulator(chain, db.as_ref(), accumulator, highest_block_number).await?
+        }
+        _ => None,
+    };
+    let accumulator = match accumulator {
+        Some(accumulator) => accumulator,
+        None => {
+            info!("counting transactions from scratch for chain {}", chain);
+            let highest_block = db
+                .load_block(chain, highest_block_number)
+                .await?
+                .expect("first block");
+            let accumulator = new_accumulator(&highest_block, window_seconds);
+            match extend_accumulator_back(chain, db.as_ref(), accumulator).await? {
+                Some(accumulator) => accumulator,
+                None => bail!("blocks changed while counting for chain {}", chain),
+            }
+        }
+    };
 
-    let mut num_txs: u64 = 0;
+    let lowest_block_number = load_lowest_known_block_number(chain, &db).await?;
+    let mut accumulator = accumulator;
+    accumulator.gap_scan =
+        update_gap_scan(chain, db.as_ref(), &accumulator, lowest_block_number).await?;
+    let gaps = accumulator
+        .gap_scan
+        .as_ref()
+        .map(|gap_scan| gap_scan.gaps.clone())
+        .unwrap_or_default();
 
-    let init_timestamp = loop {
-        let prev_block_number = current_block.prev_block_number;
+    let mut windows = vec![];
+    for window in &config.windows {
+        let window = *window;
+        let (calculation_log, window_calcs) =
+            calculate_for_window(chain, &accumulator, &gaps, window, calculating_start)?;
 
-        if prev_block_number.is_none() {
-            break current_block.timestamp;
-        }
+        debug!(
+            "done calculation over {} for chain {}: {:#?}",
+            window, chain, calculation_log
+        );
 
-        let prev_block_number = prev_block_number.unwrap();
+        db.store_calculation_log(chain, window, calculation_log)
+            .await?;
+        windows.push(window_calcs);
+    }
 
-        let prev_block = load_block(prev_block_number).await?;
+    db.store_tps_accumulator(accumulator).await?;
 
-        if prev_block.is_none() {
-            break current_block.timestamp;
-        }
+    Ok(ChainCalcs { chain, windows })
+}
 
-        let prev_block = prev_block.unwrap();
+/// Calculates the TPS over the newest part of the accumulator's window.
+fn calculate_for_window(
+    chain: Chain,
+    accumulator: &TpsAccumulator,
+    gaps: &[Gap],
+    window: Window,
+    calculating_start: DateTime<Utc>,
+) -> Result<(CalculationLog, WindowCalcs)> {
+    let latest_timestamp = accumulator.newest_block.timestamp;
+    let min_timestamp = latest_timestamp.saturating_sub(window.seconds());
+    // Like trimming, to within a bucket
+    let init_timestamp = accumulator.start_timestamp.max(min_timestamp);
+    let buckets = accumulator
+        .buckets
+        .range(init_timestamp - init_timestamp % SECONDS_PER_BUCKET..);
 
-        num_txs = num_txs
-            .checked_add(current_block.num_txs)
+    let mut num_txs: u64 = 0;
+    let mut num_system_txs: u64 = 0;
+    let mut num_failed_txs: u64 = 0;
+    let mut num_inner_txs: u64 = 0;
+    let mut num_blocks: u64 = 0;
+    let mut num_empty_blocks: u64 = 0;
+    let mut gas_used: u64 = 0;
+    let mut num_ops: u64 = 0;
+    let mut num_blocks_with_gas_used: u64 = 0;
+    let mut num_blocks_with_ops: u64 = 0;
+    let mut num_unclassified_blocks: u64 = 0;
+    let mut unclassified_seconds: u64 = 0;
+    let mut block_times = BTreeMap::new();
+    for (_, bucket) in buckets {
+        num_txs = num_txs.checked_add(bucket.num_txs).expect("overflow");
+        num_system_txs = num_system_txs
+            .checked_add(bucket.num_system_txs)
+            .expect("overflow");
+        num_failed_txs = num_failed_txs
+            .checked_add(bucket.num_failed_txs)
+            .expect("overflow");
+        num_inner_txs = num_inner_txs
+            .checked_add(bucket.num_inner_txs)
             .expect("overflow");
+        num_blocks += bucket.num_blocks;
+        num_empty_blocks += bucket.num_empty_blocks;
+        gas_used = gas_used.checked_add(bucket.gas_used).expect("overflow");
+        num_ops = num_ops.checked_add(bucket.num_ops).expect("overflow");
+        num_blocks_with_gas_used += bucket.num_blocks_with_gas_used;
+        num_blocks_with_ops += bucket.num_blocks_with_ops;
+        num_unclassified_blocks += bucket.num_unclassified_blocks;
+        unclassified_seconds = unclassified_seconds
+            .checked_add(bucket.unclassified_seconds)
+            .expect("overflow");
+        for (block_time, count) in &bucket.block_times {
+            *block_times.entry(*block_time).or_default() += count;
+        }
+    }
 
-        if prev_block.timestamp <= min_timestamp {
-            break prev_block.timestamp;
+    // Transactions of unclassified blocks aren't counted, so neither is
+    // their time
+    let classified_init_timestamp = init_timestamp
+        .saturating_add(unclassified_seconds)
+        .min(latest_timestamp);
+    let tps = calculate_tps(classified_init_timestamp, latest_timestamp, num_txs);
+    let system_tps = calculate_tps(classified_init_timestamp, latest_timestamp, num_system_txs);
+    let failed_tps = calculate_tps(classified_init_timestamp, latest_timestamp, num_failed_txs);
+    let inner_tps = calculate_tps(classified_init_timestamp, latest_timestamp, num_inner_txs);
+    // Transaction counts alone don't compare well across these chains.
+    // Until every block in the window reports them, a rate would be too low.
+    let gas_per_second = match chain.chain_type() {
+        ChainType::Ethers if num_blocks_with_gas_used == num_blocks => {
+            Some(calculate_tps(init_timestamp, latest_timestamp, gas_used))
         }
-        if prev_block.block_number == 0 {
-            break prev_block.timestamp;
+        ChainType::Ethers => {
+            info!(
+                "only {} of {} blocks over {} have gas used for chain {}",
+                num_blocks_with_gas_used, num_blocks, window, chain
+            );
+            None
         }
-
-        current_block = prev_block;
+        _ => None,
     };
-
-    let tps = calculate_tps(init_timestamp, latest_timestamp, num_txs)?;
+    let ops_per_second = match chain.chain_type() {
+        ChainType::Stellar if num_blocks_with_ops == num_blocks => {
+            Some(calculate_tps(init_timestamp, latest_timestamp, num_ops))
+        }
+        ChainType::Stellar => {
+            info!(
+                "only {} of {} blocks over {} have operations for chain {}",
+                num_blocks_with_ops, num_blocks, window, chain
+            );
+            None
+        }
+        _ => None,
+    };
+    let txs_per_minute = count_txs_per_minute(accumulator, init_timestamp)?;
+    let peaks = calculate_peaks(&txs_per_minute, init_timestamp)?;
+    let stats = calculate_stats(&txs_per_minute, &block_times, num_blocks, num_empty_blocks);
 
     let calculating_end = Utc::now();
 
     let newest_block_timestamp = Utc.timestamp(i64::try_from(latest_timestamp)?, 0);
     let oldest_block_timestamp = Utc.timestamp(i64::try_from(init_timestamp)?, 0);
 
+    let expected_window_seconds = window.seconds();
+    let covered_window_seconds = latest_timestamp.saturating_sub(classified_init_timestamp);
+    let num_gaps = u64::try_from(
+        gaps.iter()
+            .filter(|gap| gap.timestamp > min_timestamp)
+            .count(),
+    )?;
+    // Windows are never empty
+    let coverage_percent =
+        (covered_window_seconds as f64 / expected_window_seconds as f64 * 100.0).min(100.0);
+
+    if num_unclassified_blocks > 0 {
+        info!(
+            "{} of {} blocks over {} are from before transactions were classified for chain {}",
+            num_unclassified_blocks, num_blocks, window, chain
+        );
+    }
+
+    if covered_window_seconds < expected_window_seconds {
+        info!(
+            "chain {} only has contiguous blocks for {} of {} seconds, with {} gaps",
+            chain, covered_window_seconds, expected_window_seconds, num_gaps
+        );
+    }
+
     let calculation_log = CalculationLog {
         calculating_start,
         calculating_end,
         newest_block_timestamp,
         oldest_block_timestamp,
+        expected_window_seconds,
+        covered_window_seconds,
+        num_blocks,
+        num_unclassified_blocks,
+        num_gaps,
+        coverage_percent: Some(coverage_percent),
+        stats,
+    };
+
+    let window_calcs = WindowCalcs {
+        window,
+        tps,
+        system_tps,
+        failed_tps,
+        inner_tps,
+        peaks,
+        gas_per_second,
+        ops_per_second,
+    };
+
+    Ok((calculation_log, window_calcs))
+}
+
+/// Reuses the accumulator's last gap scan while it still holds, and
+/// otherwise scans again.
+///
+/// Walking the blocks below a gap can take thousands of loads, so a scan is
+/// kept until the oldest counted block or the lowest known block changes, or
+/// one of its gaps is filled, which only takes a load per gap to check.
+async fn update_gap_scan(
+    chain: Chain,
+    db: &dyn AsyncDb,
+    accumulator: &TpsAccumulator,
+    lowest_block_number: Option<u64>,
+) -> Result<Option<GapScan>> {
+    let oldest_block = match &accumulator.oldest_block {
+        Some(oldest_block) => oldest_block,
+        // The window is full
+        None => return Ok(None),
+    };
+    let lowest_block_number = match lowest_block_number {
+        Some(lowest_block_number) => lowest_block_number,
+        // No telling gaps from the end of the stored blocks
+        None => return Ok(None),
+    };
+
+    if let Some(gap_scan) = &accumulator.gap_scan {
+        if gap_scan.oldest_block.block_number == oldest_block.block_number
+            && gap_scan.oldest_block.hash == oldest_block.hash
+            && gap_scan.lowest_block_number == lowest_block_number
+        {
+            let mut is_unchanged = true;
+            for gap in &gap_scan.gaps {
+                if db
+                    .load_block(chain, gap.missing_block_number)
+                    .await?
+                    .is_some()
+                {
+                    is_unchanged = false;
+                    break;
+                }
+            }
+            if is_unchanged {
+                return Ok(Some(gap_scan.clone()));
+            }
+        }
+    }
+
+    let gaps = find_gaps(chain, db, accumulator, oldest_block, lowest_block_number).await?;
+
+    Ok(Some(GapScan {
+        oldest_block: oldest_block.clone(),
+        lowest_block_number,
+        gaps,
+    }))
+}
+
+/// Finds the gaps in the stored blocks between the oldest counted block and
+/// the start of the accumulator's window.
+///
+/// Counting stops at the first gap, but the blocks below it are still walked,
+/// to find how many more there are. Missing blocks below the lowest known
+/// block are the end of the stored blocks, not a gap.
+async fn find_gaps(
+    chain: Chain,
+    db: &dyn AsyncDb,
+    accumulator: &TpsAccumulator,
+    oldest_block: &BlockRef,
+    lowest_block_number: u64,
+) -> Result<Vec<Gap>> {
+    let mut gaps = vec![];
+
+    let min_timestamp = window_start(accumulator);
+
+    let mut next_block_number = Some(oldest_block.block_number);
+    while let Some(block_number) = next_block_number {
+        let mut blocks = BlockWalker::new(db, chain, block_number);
+        let mut last_block = None;
+        while let Some(block) = blocks.next().await? {
+            if block.timestamp <= min_timestamp {
+                return Ok(gaps);
+            }
+            last_block = Some(block);
+        }
+
+        let last_block = match last_block {
+            Some(last_block) => last_block,
+            None => break,
+        };
+        let missing_block_number = match last_block.prev_block_number {
+            Some(missing_block_number) if missing_block_number >= lowest_block_number => {
+                missing_block_number
+            }
+            _ => break,
+        };
+        gaps.push(Gap {
+            missing_block_number,
+            timestamp: last_block.timestamp,
+        });
+
+        // Probe down to the next stored block
+        let probe_end = missing_block_number
+            .saturating_sub(MAX_GAP_BLOCKS)
+            .max(lowest_block_number);
+        next_block_number = None;
+        for block_number in (probe_end..missing_block_number).rev() {
+            if db.load_block(chain, block_number).await?.is_some() {
+                next_block_number = Some(block_number);
+                break;
+            }
+        }
+    }
+
+    Ok(gaps)
+}
+
+/// The transactions in every minute from the one `init_timestamp` is in to
+/// the newest counted block's, including minutes without blocks.
+///
+/// Timestamps don't always increase, so buckets after the newest block's
+/// minute are left out, and there are no minutes if it is before
+/// `init_timestamp`'s.
+fn count_txs_per_minute(accumulator: &TpsAccumulator, init_timestamp: u64) -> Result<Vec<u64>> {
+    let first_minute = init_timestamp - init_timestamp % SECONDS_PER_BUCKET;
+    let latest_timestamp = accumulator.newest_block.timestamp;
+    let last_minute = latest_timestamp - latest_timestamp % SECONDS_PER_BUCKET;
+    if first_minute > last_minute {
+        return Ok(vec![]);
+    }
+
+    let num_minutes =
+        usize::try_from(last_minute.saturating_sub(first_minute) / SECONDS_PER_BUCKET + 1)?;
+    let mut txs_per_minute = vec![0; num_minutes];
+    for (minute, bucket) in accumulator.buckets.range(first_minute..=last_minute) {
+        let index = usize::try_from((minute - first_minute) / SECONDS_PER_BUCKET)?;
+        txs_per_minute[index] = bucket.num_txs;
+    }
+
+    Ok(txs_per_minute)
+}
+
+/// Finds the busiest span of each peak interval in `txs_per_minute`,
+/// which starts in the minute `init_timestamp` is in.
+///
+/// Spans start and end on the minute, like the buckets they are summed from.
+/// Intervals longer than the counted minutes are skipped.
+fn calculate_peaks(txs_per_minute: &[u64], init_timestamp: u64) -> Result<Vec<PeakTps>> {
+    let first_minute = init_timestamp - init_timestamp % SECONDS_PER_BUCKET;
+
+    let mut peaks = vec![];
+    for interval in Window::peak_intervals() {
+        let interval_minutes = usize::try_from(interval.seconds() / SECONDS_PER_BUCKET)?;
+        if interval_minutes > txs_per_minute.len() {
+            continue;
+        }
+
+        let mut num_txs: u64 = txs_per_minute[..interval_minutes].iter().sum();
+        let mut peak_num_txs = num_txs;
+        let mut peak_index = 0;
+        for index in interval_minutes..txs_per_minute.len() {
+            num_txs += txs_per_minute[index];
+            num_txs -= txs_per_minute[index - interval_minutes];
+            if num_txs > peak_num_txs {
+                peak_num_txs = num_txs;
+                peak_index = index + 1 - interval_minutes;
+            }
+        }
+
+        let start = first_minute + u64::try_from(peak_index)? * SECONDS_PER_BUCKET;
+        let end = start + interval.seconds();
+        peaks.push(PeakTps {
+            interval,
+            tps: calculate_tps(start, end, peak_num_txs),
+            start: Utc.timestamp(i64::try_from(start)?, 0),
+            end: Utc.timestamp(i64::try_from(end)?, 0),
+        });
+    }
+
+    Ok(peaks)
+}
+
+/// Summarizes how transactions and blocks were spread over the window,
+/// `None` if there were no blocks or no minutes.
+///
+/// Percentiles are nearest-rank. The first and last minutes may be partly
+/// outside the window, so they are counted as whole minutes, like the rest.
+fn calculate_stats(
+    txs_per_minute: &[u64],
+    block_times: &BTreeMap<u64, u64>,
+    num_blocks: u64,
+    num_empty_blocks: u64,
+) -> Option<TpsStats> {
+    if num_blocks == 0 || txs_per_minute.is_empty() {
+        return None;
+    }
+
+    let mut txs_per_minute = txs_per_minute.to_vec();
+    txs_per_minute.sort_unstable();
+    let minute_tps = |percentile: f64| {
+        let rank = nearest_rank(percentile, txs_per_minute.len() as u64);
+        txs_per_minute[rank as usize] as f64 / SECONDS_PER_BUCKET as f64
+    };
+
+    // Every counted block has a block time
+    let block_time_median = {
+        let rank = nearest_rank(0.5, num_blocks);
+        let mut blocks_below = 0;
+        let mut median = 0;
+        for (block_time, count) in block_times {
+            blocks_below += count;
+            if blocks_below > rank {
+                median = *block_time;
+                break;
+            }
+        }
+        median as f64
+    };
+    let block_time_mean = block_times
+        .iter()
+        .map(|(block_time, count)| *block_time as f64 * *count as f64)
+        .sum::<f64>()
+        / num_blocks as f64;
+    let block_time_variance = block_times
+        .iter()
+        .map(|(block_time, count)| (*block_time as f64 - block_time_mean).powi(2) * *count as f64)
+        .sum::<f64>()
+        / num_blocks as f64;
+
+    Some(TpsStats {
+        tps_p50: minute_tps(0.5),
+        tps_p90: minute_tps(0.9),
+        tps_p99: minute_tps(0.99),
+        block_time_mean,
+        block_time_median,
+        block_time_std_dev: block_time_variance.sqrt(),
+        empty_block_ratio: num_empty_blocks as f64 / num_blocks as f64,
+    })
+}
+
+/// The index of the `percentile` value among `len` sorted values.
+fn nearest_rank(percentile: f64, len: u64) -> u64 {
+    let rank = (percentile * len as f64).ceil() as u64;
+    rank.clamp(1, len) - 1
+}
+
+/// An accumulator that has counted nothing, with its window both starting
+/// and ending at `highest_block`.
+fn new_accumulator(highest_block: &Block, window_seconds: u64) -> TpsAccumulator {
+    TpsAccumulator {
+        version: ACCUMULATOR_VERSION,
+        chain: highest_block.chain,
+        window_seconds,
+        newest_block: BlockRef::from(highest_block),
+        oldest_block: Some(BlockRef::from(highest_block)),
+        start_timestamp: highest_block.timestamp,
+        has_system_txs: highest_block.num_system_txs.is_some(),
+        has_failed_txs: highest_block.num_failed_txs.is_some(),
+        buckets: Default::default(),
+        gap_scan: None,
+    }
+}
+
+/// Counts blocks imported since the last calculation, and any older blocks
+/// that now fill out the window.
+///
+/// Returns `None` if the stored blocks no longer connect to the counted
+/// blocks, after a reorg or a gap, and the count must start over.
+async fn update_accumulator(
+    chain: Chain,
+    db: &dyn AsyncDb,
+    mut accumulator: TpsAccumulator,
+    highest_block_number: u64,
+) -> Result<Option<TpsAccumulator>> {
+    let newest_block = &accumulator.newest_block;
+    if highest_block_number < newest_block.block_number {
+        debug!(
+            "highest block {} is below the newest counted block {} for chain {}",
+            highest_block_number, newest_block.block_number, chain
+        );
+        return Ok(None);
+    }
+
+    let mut new_blocks = vec![];
+    let mut blocks = BlockWalker::new(db, chain, highest_block_number);
+    loop {
+        let block = match blocks.next().await? {
+            Some(block) => block,
+            None => {
+                debug!(
+                    "new blocks don't reach the newest counted block for chain {}",
+                    chain
+                );
+                return Ok(None);
+            }
+        };
+
+        // Older blocks were counted as unclassified
+        if (block.num_system_txs.is_some() && !accumulator.has_system_txs)
+            || (block.num_failed_txs.is_some() && !accumulator.has_failed_txs)
+        {
+            debug!(
+                "block {} tells apart transactions the counted blocks didn't for chain {}",
+                block.block_number, chain
+            );
+            return Ok(None);
+        }
+
+        if block.block_number <= newest_block.block_number {
+            if block.block_number != newest_block.block_number || block.hash != newest_block.hash {
+                debug!(
+                    "newest counted block {} was replaced for chain {}",
+                    newest_block.block_number, chain
+                );
+                return Ok(None);
+            }
+            break;
+        }
+
+        new_blocks.push(block);
+    }
+
+    // Each new block's previous block is the next one, or the newest
+    // counted block for the last.
+    let prev_timestamps = new_blocks
+        .iter()
+        .skip(1)
+        .map(|block| block.timestamp)
+        .chain(Some(newest_block.timestamp))
+        .collect::<Vec<_>>();
+    for (block, prev_timestamp) in new_blocks.iter().zip(prev_timestamps) {
+        add_block(&mut accumulator, block, prev_timestamp);
+    }
+    if let Some(highest_block) = new_blocks.first() {
+        accumulator.newest_block = BlockRef::from(highest_block);
+    }
+
+    extend_accumulator_back(chain, db, accumulator).await
+}
+
+/// Counts blocks back from the start of the window until it is full, the
+/// stored blocks run out, or genesis.
+///
+/// Returns `None` if the block the window starts at was replaced.
+async fn extend_accumulator_back(
+    chain: Chain,
+    db: &dyn AsyncDb,
+    mut accumulator: TpsAccumulator,
+) -> Result<Option<TpsAccumulator>> {
+    let oldest_block = match &accumulator.oldest_block {
+        Some(oldest_block) => oldest_block.clone(),
+        // The window is full, but new blocks may have pushed old ones out
+        None => return Ok(Some(trim_accumulator(accumulator))),
     };
 
-    log::debug!(
-        "done calculation for chain {}: {:#?}",
-        chain,
-        calculation_log
-    );
+    let min_timestamp = window_start(&accumulator);
+    if oldest_block.timestamp <= min_timestamp {
+        return Ok(Some(trim_accumulator(accumulator)));
+    }
+
+    let mut blocks = BlockWalker::new(db, chain, oldest_block.block_number);
+    let mut current_block = match blocks.next().await? {
+        Some(block) if block.hash == oldest_block.hash => block,
+        _ => {
+            debug!(
+                "block {} at the start of the window was replaced for chain {}",
+                oldest_block.block_number, chain
+            );
+            return Ok(None);
+        }
+    };
+
+    // The window starts at the block before the oldest counted block,
+    // so a block is counted once its previous block is found.
+    loop {
+        let prev_block = match blocks.next().await? {
+            Some(prev_block) => prev_block,
+            None => break,
+        };
+
+        add_block(&mut accumulator, &current_block, prev_block.timestamp);
+        current_block = prev_block;
+
+        if current_block.timestamp <= min_timestamp || current_block.block_number == 0 {
+            break;
+        }
+    }
+
+    accumulator.oldest_block = Some(BlockRef::from(&current_block));
+    accumulator.start_timestamp = current_block.timestamp;
+
+    Ok(Some(trim_accumulator(accumulator)))
+}
+
+/// Subtracts blocks that have fallen out of the window.
+///
+/// Blocks are subtracted a bucket at a time, so up to a minute of blocks
+/// before the start of a full window may still be counted.
+fn trim_accumulator(mut accumulator: TpsAccumulator) -> TpsAccumulator {
+    let min_timestamp = window_start(&accumulator);
+    if accumulator.start_timestamp >= min_timestamp {
+        return accumulator;
+    }
+
+    accumulator.buckets = accumulator
+        .buckets
+        .split_off(&(min_timestamp - min_timestamp % SECONDS_PER_BUCKET));
+    accumulator.start_timestamp = min_timestamp;
+    accumulator.oldest_block = None;
+
+    accumulator
+}
+
+fn add_block(accumulator: &mut TpsAccumulator, block: &Block, prev_timestamp: u64) {
+    let is_unclassified = (accumulator.has_system_txs && block.num_system_txs.is_none())
+        || (accumulator.has_failed_txs && block.num_failed_txs.is_none());
+    let bucket_start = block.timestamp - block.timestamp % SECONDS_PER_BUCKET;
+    let bucket = accumulator.buckets.entry(bucket_start).or_default();
+    // Timestamps don't always increase
+    let block_time = block.timestamp.saturating_sub(prev_timestamp);
+    *bucket.block_times.entry(block_time).or_default() += 1;
+    bucket.num_blocks += 1;
+    if block.num_txs == 0 {
+        bucket.num_empty_blocks += 1;
+    }
+    if let Some(gas_used) = block.gas_used {
+        bucket.gas_used = bucket.gas_used.checked_add(gas_used).expect("overflow");
+        bucket.num_blocks_with_gas_used += 1;
+    }
+    if let Some(num_ops) = block.num_ops {
+        bucket.num_ops = bucket.num_ops.checked_add(num_ops).expect("overflow");
+        bucket.num_blocks_with_ops += 1;
+    }
+
+    // Its user transactions may include system or failed transactions
+    if is_unclassified {
+        bucket.num_unclassified_blocks += 1;
+        bucket.unclassified_seconds = bucket
+            .unclassified_seconds
+            .checked_add(block_time)
+            .expect("overflow");
+        return;
+    }
 
-    write_calculation_log(chain, &db, calculation_log).await?;
+    bucket.num_txs = bucket.num_txs.checked_add(block.num_txs).expect("overflow");
+    let num_system_txs = block.num_system_txs.unwrap_or(0);
+    bucket.num_system_txs = bucket
+        .num_system_txs
+        .checked_add(num_system_txs)
+        .expect("overflow");
+    let num_failed_txs = block.num_failed_txs.unwrap_or(0);
+    bucket.num_failed_txs = bucket
+        .num_failed_txs
+        .checked_add(num_failed_txs)
+        .expect("overflow");
+    let num_inner_txs = block.num_inner_txs.unwrap_or(0);
+    bucket.num_inner_txs = bucket
+        .num_inner_txs
+        .checked_add(num_inner_txs)
+        .expect("overflow");
+}
 
-    Ok(ChainCalcs { chain, tps })
+fn window_start(accumulator: &TpsAccumulator) -> u64 {
+    // Local devnets may not be a week old, or even have real timestamps
+    accumulator
+        .newest_block
+        .timestamp
+        .saturating_sub(accumulator.window_seconds)
 }
 
-fn calculate_tps(init_timestamp: u64, latest_timestamp: u64, num_txs: u64) -> Result<f64> {
+/// The average TPS over the seconds from `init_timestamp` to
+/// `latest_timestamp`, or 0 if there are none.
+///
+/// Also used for other counts per second, like gas.
+fn calculate_tps(init_timestamp: u64, latest_timestamp: u64, num_txs: u64) -> f64 {
     let total_seconds = latest_timestamp.saturating_sub(init_timestamp);
-    let total_seconds_u32 =
-        u32::try_from(total_seconds).map_err(|_| anyhow!("seconds overflows u32"))?;
-    let num_txs_u32 = u32::try_from(num_txs).map_err(|_| anyhow!("num txs overflows u32"))?;
-    let total_seconds_f64 = f64::from(total_seconds_u32);
-    let num_txs_f64 = f64::from(num_txs_u32);
-    let mut tps = num_txs_f64 / total_seconds_f64;
+    if total_seconds == 0 {
+        return 0.0;
+    }
+
+    // Divide in integers first, so that only the fraction is rounded
+    // when counts are too big for an f64 to hold exactly
+    let whole_tps = num_txs / total_seconds;
+    let remaining_txs = num_txs % total_seconds;
+
+    whole_tps as f64 + remaining_txs as f64 / total_seconds as f64
+}
+
+#[cfg(test)]
+mod test_calculate {
+    use super::{
+        calculate_for_window, calculate_peaks, calculate_stats, calculate_tps,
+        count_txs_per_minute, extend_accumulator_back, nearest_rank, new_accumulator,
+        update_accumulator, update_gap_scan, ACCUMULATOR_VERSION,
+    };
+    use crate::async_db::{
+        test_async_db::{block, MemoryDb},
+        AsyncDb,
+    };
+    use chrono::Utc;
+    use realtps_common::{
+        chain::Chain,
+        db::{BlockRef, TpsAccumulator, TpsBucket},
+        window::Window,
+    };
+    use std::collections::BTreeMap;
+
+    /// An accumulator whose newest block is at `newest_timestamp`, with
+    /// buckets of `(minute, num_txs)`.
+    fn accumulator(newest_timestamp: u64, buckets: &[(u64, u64)]) -> TpsAccumulator {
+        TpsAccumulator {
+            version: ACCUMULATOR_VERSION,
+            chain: Chain::Ethereum,
+            window_seconds: Window::default().seconds(),
+            newest_block: BlockRef {
+                block_number: 100,
+                hash: "100".to_string(),
+                timestamp: newest_timestamp,
+            },
+            oldest_block: None,
+            start_timestamp: 0,
+            has_system_txs: false,
+            has_failed_txs: false,
+            buckets: buckets
+                .iter()
+                .map(|(minute, num_txs)| {
+                    let bucket = TpsBucket {
+                        num_txs: *num_txs,
+                        num_blocks: 1,
+                        ..TpsBucket::default()
+                    };
+                    (*minute, bucket)
+                })
+                .collect(),
+            gap_scan: None,
+        }
+    }
+
+    /// Counts from scratch back from `block_number`, like a first calculation.
+    async fn count_back(
+        db: &MemoryDb,
+        block_number: u64,
+        window_seconds: u64,
+    ) -> anyhow::Result<TpsAccumulator> {
+        let highest_block = db
+            .load_block(Chain::Ethereum, block_number)
+            .await?
+            .expect("block");
+        let accumulator = new_accumulator(&highest_block, window_seconds);
+        let accumulator = extend_accumulator_back(Chain::Ethereum, db, accumulator).await?;
+        Ok(accumulator.expect("accumulator"))
+    }
+
+    fn num_txs(accumulator: &TpsAccumulator) -> u64 {
+        accumulator
+            .buckets
+            .values()
+            .map(|bucket| bucket.num_txs)
+            .sum()
+    }
+
+    #[test]
+    fn tps() {
+        assert_eq!(calculate_tps(100, 110, 25), 2.5);
+        assert_eq!(calculate_tps(100, 110, 0), 0.0);
+    }
+
+    #[test]
+    fn zero_duration() {
+        assert_eq!(calculate_tps(100, 100, 0), 0.0);
+        assert_eq!(calculate_tps(100, 100, 25), 0.0);
+    }
+
+    #[test]
+    fn timestamps_out_of_order() {
+        // Devnets may not have real timestamps
+        assert_eq!(calculate_tps(110, 100, 25), 0.0);
+    }
+
+    #[test]
+    fn more_txs_than_u32() {
+        let num_txs = u64::from(u32::MAX) * 3;
+        assert_eq!(calculate_tps(0, 3, num_txs), f64::from(u32::MAX));
+    }
+
+    #[test]
+    fn more_seconds_than_u32() {
+        let total_seconds = u64::from(u32::MAX) + 1;
+        assert_eq!(calculate_tps(0, total_seconds, total_seconds * 2), 2.0);
+        assert_eq!(calculate_tps(0, total_seconds, total_seconds / 2), 0.5);
+    }
+
+    #[test]
+    fn more_txs_than_f64_holds_exactly() {
+        // 2^53 + 3 rounds to 2^53 + 4 as an f64, which divides to a whole
+        // number, but the true TPS is closer to 3002399751580331.5
+        let num_txs = (1 << 53) + 3;
+        assert_eq!(calculate_tps(0, 3, num_txs), 3002399751580331.5);
+
+        assert_eq!(calculate_tps(0, 1, u64::MAX), u64::MAX as f64);
+        assert_eq!(calculate_tps(0, u64::MAX, u64::MAX), 1.0);
+    }
+
+    #[test]
+    fn txs_per_minute() -> anyhow::Result<()> {
+        let accumulator = accumulator(190, &[(60, 3), (180, 5)]);
+        assert_eq!(count_txs_per_minute(&accumulator, 90)?, vec![3, 0, 5]);
+        Ok(())
+    }
+
+    #[test]
+    fn txs_per_minute_after_newest_block() -> anyhow::Result<()> {
+        // A block earlier in the chain was timestamped after the newest
+        let accumulator = accumulator(130, &[(60, 3), (120, 4), (300, 5)]);
+        assert_eq!(count_txs_per_minute(&accumulator, 60)?, vec![3, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn txs_per_minute_newest_block_before_init() -> anyhow::Result<()> {
+        let accumulator = accumulator(100, &[(60, 3), (300, 5)]);
+        assert_eq!(count_txs_per_minute(&accumulator, 300)?, Vec::<u64>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn peaks() -> anyhow::Result<()> {
+        let mut txs_per_minute = vec![0; 70];
+        txs_per_minute[20] = 7200;
+        let peaks = calculate_peaks(&txs_per_minute, 90)?;
+        let peaks: Vec<_> = peaks
+            .iter()
+            .map(|peak| (peak.interval, peak.tps, peak.start.timestamp()))
+            .collect();
+        let intervals = Window::peak_intervals();
+        assert_eq!(
+            peaks,
+            vec![
+                (intervals[0], 120.0, 60 + 20 * 60),
+                (intervals[1], 12.0, 60 + 11 * 60),
+                (intervals[2], 2.0, 60),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn peaks_over_fewer_minutes_than_intervals() -> anyhow::Result<()> {
+        let peaks = calculate_peaks(&[1, 2, 3], 0)?;
+        assert_eq!(peaks.len(), 1);
+        assert_eq!(peaks[0].interval, Window::peak_intervals()[0]);
+        assert_eq!(peaks[0].start.timestamp(), 120);
+
+        assert!(calculate_peaks(&[], 0)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn nearest_rank_edges() {
+        assert_eq!(nearest_rank(0.5, 1), 0);
+        assert_eq!(nearest_rank(0.99, 1), 0);
+        assert_eq!(nearest_rank(0.0, 5), 0);
+        assert_eq!(nearest_rank(1.0, 5), 4);
+        assert_eq!(nearest_rank(0.5, 3), 1);
+        assert_eq!(nearest_rank(0.5, 4), 1);
+        assert_eq!(nearest_rank(0.9, 10), 8);
+        assert_eq!(nearest_rank(0.99, 10), 9);
+        assert_eq!(nearest_rank(0.99, 100), 98);
+    }
+
+    #[test]
+    fn stats_without_blocks() {
+        assert!(calculate_stats(&[0, 0], &BTreeMap::new(), 0, 0).is_none());
+        assert!(calculate_stats(&[], &BTreeMap::from([(2, 1)]), 1, 0).is_none());
+    }
+
+    #[test]
+    fn stats_of_one_minute() {
+        let stats = calculate_stats(&[120], &BTreeMap::from([(2, 1)]), 1, 0).expect("stats");
+        assert_eq!(stats.tps_p50, 2.0);
+        assert_eq!(stats.tps_p90, 2.0);
+        assert_eq!(stats.tps_p99, 2.0);
+        assert_eq!(stats.block_time_mean, 2.0);
+        assert_eq!(stats.block_time_median, 2.0);
+        assert_eq!(stats.block_time_std_dev, 0.0);
+        assert_eq!(stats.empty_block_ratio, 0.0);
+    }
+
+    #[test]
+    fn stats_percentiles_of_few_minutes() {
+        let block_times = BTreeMap::from([(12, 3)]);
+        let stats = calculate_stats(&[180, 60, 120], &block_times, 3, 1).expect("stats");
+        assert_eq!(stats.tps_p50, 2.0);
+        assert_eq!(stats.tps_p90, 3.0);
+        // Not interpolated past the busiest minute
+        assert_eq!(stats.tps_p99, 3.0);
+        assert_eq!(stats.empty_block_ratio, 1.0 / 3.0);
+    }
+
+    #[test]
+    fn stats_block_time_median() {
+        let block_times = BTreeMap::from([(1, 2), (5, 1), (10, 2)]);
+        let stats = calculate_stats(&[0], &block_times, 5, 0).expect("stats");
+        assert_eq!(stats.block_time_median, 5.0);
+        assert_eq!(stats.block_time_mean, 5.4);
 
-    // Special float values will not serialize sensibly
-    if tps.is_nan() || tps.is_infinite() {
-        tps = 0.0;
+        // The lower of the middle two
+        let block_times = BTreeMap::from([(1, 2), (3, 2)]);
+        let stats = calculate_stats(&[0], &block_times, 4, 0).expect("stats");
+        assert_eq!(stats.block_time_median, 1.0);
+        assert_eq!(stats.block_time_mean, 2.0);
+        assert_eq!(stats.block_time_std_dev, 1.0);
     }
 
-    Ok(tps)
+    #[tokio::test]
+    async fn append_blocks() -> anyhow::Result<()> {
+        let timestamps: Vec<u64> = (0..10).map(|n| n * 10).collect();
+        let db = MemoryDb::with_blocks(Chain::Ethereum, 0, &timestamps);
+        let window_seconds = Window::default().seconds();
+
+        let accumulator = count_back(&db, 5, window_seconds).await?;
+        // Genesis starts the window, so isn't counted
+        assert_eq!(num_txs(&accumulator), 5);
+        assert_eq!(accumulator.start_timestamp, 0);
+
+        let accumulator = update_accumulator(Chain::Ethereum, &db, accumulator, 9)
+            .await?
+            .expect("accumulator");
+        assert_eq!(num_txs(&accumulator), 9);
+        assert_eq!(accumulator.newest_block.block_number, 9);
+
+        let from_scratch = count_back(&db, 9, window_seconds).await?;
+        assert_eq!(
+            serde_json::to_value(&accumulator.buckets)?,
+            serde_json::to_value(&from_scratch.buckets)?
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn newest_block_replaced() -> anyhow::Result<()> {
+        let timestamps: Vec<u64> = (0..6).map(|n| n * 10).collect();
+        let db = MemoryDb::with_blocks(Chain::Ethereum, 0, &timestamps);
+        let accumulator = count_back(&db, 5, Window::default().seconds()).await?;
+
+        db.put_block(block(Chain::Ethereum, 5, 50, 1, "b"));
+        db.put_block(block(Chain::Ethereum, 6, 60, 1, "b"));
+        db.set_highest_block_number(Chain::Ethereum, 6);
+        let accumulator = update_accumulator(Chain::Ethereum, &db, accumulator, 6).await?;
+        assert!(accumulator.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oldest_block_replaced() -> anyhow::Result<()> {
+        // Blocks before the 3rd weren't imported yet
+        let timestamps: Vec<u64> = (0..6).map(|n| 1000 + n * 10).collect();
+        let db = MemoryDb::with_blocks(Chain::Ethereum, 3, &timestamps);
+        let accumulator = count_back(&db, 8, Window::default().seconds()).await?;
+        let oldest_block = accumulator.oldest_block.clone().expect("oldest block");
+        assert_eq!(oldest_block.block_number, 3);
+
+        db.put_block(block(Chain::Ethereum, 3, 1000, 1, "b"));
+        db.put_block(block(Chain::Ethereum, 2, 990, 1, "b"));
+        let accumulator = extend_accumulator_back(Chain::Ethereum, &db, accumulator).await?;
+        assert!(accumulator.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn trim_blocks_out_of_window() -> anyhow::Result<()> {
+        let timestamps: Vec<u64> = (0..14).map(|n| n * 60).collect();
+        let db = MemoryDb::with_blocks(Chain::Ethereum, 0, &timestamps);
+
+        let accumulator = count_back(&db, 10, 100).await?;
+        assert!(accumulator.oldest_block.is_none());
+        assert_eq!(accumulator.start_timestamp, 500);
+        assert_eq!(
+            accumulator.buckets.keys().cloned().collect::<Vec<_>>(),
+            vec![540, 600]
+        );
+
+        let accumulator = update_accumulator(Chain::Ethereum, &db, accumulator, 13)
+            .await?
+            .expect("accumulator");
+        assert_eq!(accumulator.start_timestamp, 680);
+        assert_eq!(
+            accumulator.buckets.keys().cloned().collect::<Vec<_>>(),
+            vec![660, 720, 780]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gap_scan_reused_until_gap_filled() -> anyhow::Result<()> {
+        let timestamps: Vec<u64> = (0..100).map(|n| 1000 + n * 10).collect();
+        let db = MemoryDb::with_blocks(Chain::Ethereum, 0, &timestamps);
+        db.remove_blocks(Chain::Ethereum, vec![20, 21, 60]).await?;
+        let mut accumulator = count_back(&db, 99, Window::default().seconds()).await?;
+        let oldest_block = accumulator.oldest_block.clone().expect("oldest block");
+        assert_eq!(oldest_block.block_number, 61);
+
+        let gap_scan = update_gap_scan(Chain::Ethereum, &db, &accumulator, Some(0)).await?;
+        let gap_scan = gap_scan.expect("gap scan");
+        let gaps: Vec<_> = gap_scan
+            .gaps
+            .iter()
+            .map(|gap| (gap.missing_block_number, gap.timestamp))
+            .collect();
+        assert_eq!(gaps, vec![(60, 1610), (21, 1220)]);
+        accumulator.gap_scan = Some(gap_scan);
+
+        // Checked with a load per gap
+        let num_blocks_loaded = *db.num_blocks_loaded.lock().expect("poisoned");
+        let gap_scan = update_gap_scan(Chain::Ethereum, &db, &accumulator, Some(0)).await?;
+        assert_eq!(gap_scan.expect("gap scan").gaps.len(), 2);
+        assert_eq!(
+            *db.num_blocks_loaded.lock().expect("poisoned"),
+            num_blocks_loaded
+        );
+
+        // Scanned again once part of a gap is filled
+        db.put_block(block(Chain::Ethereum, 21, 1210, 1, ""));
+        let gap_scan = update_gap_scan(Chain::Ethereum, &db, &accumulator, Some(0)).await?;
+        let gaps: Vec<_> = gap_scan
+            .expect("gap scan")
+            .gaps
+            .iter()
+            .map(|gap| (gap.missing_block_number, gap.timestamp))
+            .collect();
+        assert_eq!(gaps, vec![(60, 1610), (20, 1210)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unclassified_blocks_not_counted() -> anyhow::Result<()> {
+        let timestamps: Vec<u64> = (0..10).map(|n| n * 10).collect();
+        let db = MemoryDb::with_blocks(Chain::Arbitrum, 0, &timestamps);
+        // Blocks from the 6th on tell system transactions apart
+        for block_number in 6..10 {
+            let mut block = block(Chain::Arbitrum, block_number, block_number * 10, 1, "");
+            block.num_system_txs = Some(1);
+            db.put_block(block);
+        }
+
+        let highest_block = db.load_block(Chain::Arbitrum, 9).await?.expect("block");
+        let accumulator = new_accumulator(&highest_block, Window::default().seconds());
+        let accumulator = extend_accumulator_back(Chain::Arbitrum, &db, accumulator)
+            .await?
+            .expect("accumulator");
+        let (log, calcs) = calculate_for_window(
+            Chain::Arbitrum,
+            &accumulator,
+            &[],
+            Window::default(),
+            Utc::now(),
+        )?;
+        assert_eq!(log.num_blocks, 9);
+        assert_eq!(log.num_unclassified_blocks, 5);
+        assert_eq!(log.covered_window_seconds, 40);
+        assert_eq!(calcs.tps, 0.1);
+        assert_eq!(calcs.system_tps, 0.1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn newly_classified_blocks_count_again() -> anyhow::Result<()> {
+        let timestamps: Vec<u64> = (0..6).map(|n| n * 10).collect();
+        let db = MemoryDb::with_blocks(Chain::Ethereum, 0, &timestamps);
+        let accumulator = count_back(&db, 5, Window::default().seconds()).await?;
+
+        let mut block = block(Chain::Ethereum, 6, 60, 1, "");
+        block.num_system_txs = Some(1);
+        db.put_block(block);
+        let accumulator = update_accumulator(Chain::Ethereum, &db, accumulator, 6).await?;
+        assert!(accumulator.is_none());
+        Ok(())
+    }
 }
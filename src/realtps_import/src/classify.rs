@@ -0,0 +1,187 @@
+//! Tells transactions sent by users from those a chain's protocol makes for
+//! itself, like Solana votes or rollup deposits.
+//!
+//! User transactions are counted in `Block::num_txs`, the headline TPS, and
+//! system transactions in `Block::num_system_txs`. Chains without rules here
+//! count every transaction as a user transaction.
+//!
+//! The EVM transaction types counted as system transactions are set in the
+//! `[classify]` section of the RPC configuration.
+
+use realtps_common::chain::Chain;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Optimism deposits, including the L1 attributes transaction that starts
+/// every block.
+const OPTIMISM_DEPOSIT_TX_TYPE: u64 = 0x7e;
+/// Arbitrum deposits from L1.
+const ARBITRUM_DEPOSIT_TX_TYPE: u64 = 0x64;
+/// Arbitrum internal transactions, like the one starting every block.
+const ARBITRUM_INTERNAL_TX_TYPE: u64 = 0x6a;
+
+/// The bits of a Substrate extrinsic's version byte that give its type.
+const SUBSTRATE_EXTRINSIC_TYPE_MASK: u8 = 0b1100_0000;
+/// Extrinsics with neither a signature nor transaction extensions:
+/// inherents, like `timestamp.set` and parachain validation data, and the
+/// unsigned transactions a runtime validates itself, like heartbeats.
+const SUBSTRATE_BARE_EXTRINSIC_TYPE: u8 = 0b0000_0000;
+
+/// Classification settings, from the `[classify]` section of the RPC
+/// configuration.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ClassifyConfig {
+    /// The EIP-2718 transaction types of each EVM chain's system
+    /// transactions. Replaces the defaults for Arbitrum and Optimism when set.
+    #[serde(default = "default_system_tx_types")]
+    pub system_tx_types: HashMap<Chain, Vec<u64>>,
+}
+
+impl Default for ClassifyConfig {
+    fn default() -> ClassifyConfig {
+        ClassifyConfig {
+            system_tx_types: default_system_tx_types(),
+        }
+    }
+}
+
+fn default_system_tx_types() -> HashMap<Chain, Vec<u64>> {
+    HashMap::from([
+        (Chain::Optimism, vec![OPTIMISM_DEPOSIT_TX_TYPE]),
+        (
+            Chain::Arbitrum,
+            vec![ARBITRUM_DEPOSIT_TX_TYPE, ARBITRUM_INTERNAL_TX_TYPE],
+        ),
+    ])
+}
+
+impl ClassifyConfig {
+    pub fn system_tx_types(&self, chain: Chain) -> Vec<u64> {
+        self.system_tx_types
+            .get(&chain)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TxKind {
+    User,
+    System,
+}
+
+/// A block's transactions, counted by kind.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TxCounts {
+    /// Successful user transactions.
+    pub user: u64,
+    pub system: u64,
+    /// Failed user transactions, for chains that tell them apart.
+    pub failed: u64,
+}
+
+impl TxCounts {
+    pub fn add(&mut self, kind: TxKind) {
+        match kind {
+            TxKind::User => self.user += 1,
+            TxKind::System => self.system += 1,
+        }
+    }
+
+    /// Counts a transaction that failed. Failed system transactions are
+    /// still system transactions.
+    pub fn add_failed(&mut self, kind: TxKind) {
+        match kind {
+            TxKind::User => self.failed += 1,
+            TxKind::System => self.system += 1,
+        }
+    }
+}
+
+impl FromIterator<TxKind> for TxCounts {
+    fn from_iter<I: IntoIterator<Item = TxKind>>(kinds: I) -> TxCounts {
+        let mut counts = TxCounts::default();
+        for kind in kinds {
+            counts.add(kind);
+        }
+        counts
+    }
+}
+
+/// Classifies an EVM transaction by its EIP-2718 type, given the chain's
+/// system transaction types.
+pub fn ethers_tx_kind(system_tx_types: &[u64], tx_type: Option<u64>) -> TxKind {
+    match tx_type {
+        Some(tx_type) if system_tx_types.contains(&tx_type) => TxKind::System,
+        _ => TxKind::User,
+    }
+}
+
+/// Classifies a Solana transaction by the programs its instructions call.
+/// Vote transactions are made up only of vote instructions; a transaction
+/// that also does something else is a user transaction.
+pub fn solana_tx_kind<P: PartialEq>(program_ids: &[P], vote_program_id: &P) -> TxKind {
+    if !program_ids.is_empty()
+        && program_ids
+            .iter()
+            .all(|program_id| program_id == vote_program_id)
+    {
+        TxKind::System
+    } else {
+        TxKind::User
+    }
+}
+
+/// Classifies a Substrate extrinsic by its version byte. Signed extrinsics,
+/// and the newer general extrinsics, are user transactions.
+pub fn substrate_extrinsic_kind(version: u8) -> TxKind {
+    if version & SUBSTRATE_EXTRINSIC_TYPE_MASK == SUBSTRATE_BARE_EXTRINSIC_TYPE {
+        TxKind::System
+    } else {
+        TxKind::User
+    }
+}
+
+#[cfg(test)]
+mod test_classify {
+    use super::{ethers_tx_kind, solana_tx_kind, ClassifyConfig, TxKind};
+    use crate::config::RpcConfig;
+    use realtps_common::chain::Chain;
+
+    #[test]
+    fn ethers_system_tx_types() {
+        let config = ClassifyConfig::default();
+        let system_tx_types = config.system_tx_types(Chain::Arbitrum);
+        assert_eq!(ethers_tx_kind(&system_tx_types, Some(0x6a)), TxKind::System);
+        assert_eq!(ethers_tx_kind(&system_tx_types, Some(2)), TxKind::User);
+        assert_eq!(ethers_tx_kind(&system_tx_types, None), TxKind::User);
+        assert!(config.system_tx_types(Chain::Ethereum).is_empty());
+    }
+
+    #[test]
+    fn configured_system_tx_types() -> anyhow::Result<()> {
+        let config: ClassifyConfig = toml::from_str("[system_tx_types]\npolygon = [0x7f]\n")?;
+        assert_eq!(config.system_tx_types(Chain::Polygon), vec![0x7f]);
+        // Replaces the defaults
+        assert!(config.system_tx_types(Chain::Optimism).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn default_system_tx_types_when_unset() -> anyhow::Result<()> {
+        let config: RpcConfig = toml::from_str("[chains]\n[classify]\n")?;
+        let config = config.classify;
+        assert_eq!(config.system_tx_types(Chain::Optimism), vec![0x7e]);
+        assert_eq!(config.system_tx_types(Chain::Arbitrum), vec![0x64, 0x6a]);
+        Ok(())
+    }
+
+    #[test]
+    fn solana_votes() {
+        let vote = 1;
+        assert_eq!(solana_tx_kind(&[vote, vote], &vote), TxKind::System);
+        assert_eq!(solana_tx_kind(&[vote, 2], &vote), TxKind::User);
+        assert_eq!(solana_tx_kind(&[2], &vote), TxKind::User);
+        assert_eq!(solana_tx_kind(&[], &vote), TxKind::User);
+    }
+}
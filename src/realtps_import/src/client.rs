@@ -1,11 +1,176 @@
-use anyhow::Result;
+use crate::classify::ClassifyConfig;
+use crate::clients::*;
+use crate::config::RpcConfig;
+use crate::delay::retry_if_err;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
-use realtps_common::db::Block;
+use futures::future::FutureExt;
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::{error, info};
+use realtps_common::{
+    chain::{Chain, ChainType},
+    db::Block,
+};
+use std::collections::HashMap;
+use tokio::task;
 
 #[async_trait]
 pub trait Client: Send + Sync + 'static {
     async fn client_version(&self) -> Result<String>;
     async fn get_latest_block_number(&self) -> Result<u64>;
+    /// Returns the number of the highest block the network considers final,
+    /// or `None` if the client doesn't know about finality.
+    async fn get_finalized_block_number(&self) -> Result<Option<u64>> {
+        Ok(None)
+    }
     /// Returns `None` if the network thinks the block doesn't exist
     async fn get_block(&self, block_number: u64) -> Result<Option<Block>>;
 }
+
+/// Creates clients for each of the chains, from their RPC URLs in the config.
+///
+/// Chains whose client can't be created are logged and left out.
+pub async fn make_all_clients(
+    chains: &[Chain],
+    rpc_config: &RpcConfig,
+) -> Result<HashMap<Chain, Box<dyn Client>>> {
+    let mut client_futures = FuturesUnordered::new();
+
+    for chain in chains {
+        let rpc_url = match rpc_config.chains.get(chain) {
+            Some(rpc_url) => rpc_url.to_string(),
+            None => {
+                error!("no RPC URL configured for {}", chain);
+                continue;
+            }
+        };
+        let classify_config = rpc_config.classify.clone();
+        let client_future = task::spawn(make_client(*chain, rpc_url, classify_config));
+        let client_future = client_future.map(move |client| (*chain, client));
+        client_futures.push(client_future);
+    }
+
+    let mut clients = HashMap::new();
+
+    while let Some((chain, client)) = client_futures.next().await {
+        let client = client?;
+        match client {
+            Ok(Some(client)) => {
+                clients.insert(chain, client);
+            }
+            Ok(None) => { /* pass */ }
+            Err(e) => {
+                error!("{}", e);
+            }
+        }
+    }
+
+    Ok(clients)
+}
+
+/// Creates the client for the chain's type, and checks that the node
+/// responds.
+pub async fn make_client(
+    chain: Chain,
+    rpc_url: String,
+    classify_config: ClassifyConfig,
+) -> Result<Option<Box<dyn Client>>> {
+    info!("creating client for {} at {}", chain, rpc_url);
+
+    let client: Option<Box<dyn Client>> = match chain.chain_type() {
+        ChainType::Algorand => Some(Box::new(AlgorandClient::new(&rpc_url)?)),
+        ChainType::Esplora => Some(Box::new(EsploraClient::new(&rpc_url)?)),
+        ChainType::MultiversX => Some(Box::new(MultiversXClient::new(&rpc_url)?)),
+        ChainType::Ethers => Some(Box::new(EthersClient::new(
+            chain,
+            &rpc_url,
+            classify_config.system_tx_types(chain),
+        )?)),
+        ChainType::Hedera => Some(Box::new(HederaClient::new(&rpc_url)?)),
+        ChainType::Near => Some(Box::new(NearClient::new(&rpc_url)?)),
+        ChainType::Pivx => Some(Box::new(PivxClient::new(&rpc_url)?)),
+        ChainType::Solana => Some(Box::new(SolanaClient::new(&rpc_url)?)),
+        ChainType::Stellar => Some(Box::new(StellarClient::new(&rpc_url)?)),
+        ChainType::Tendermint => Some(Box::new(TendermintClient::new(chain, &rpc_url)?)),
+        ChainType::Substrate => Some(Box::new(SubstrateClient::new(chain, &rpc_url).await?)),
+    };
+
+    if let Some(ref client) = client {
+        let version = retry_if_err(chain, || client.client_version())
+            .await
+            .context(format!("error getting client version for {}", chain))?;
+        info!("node version for chain {}: {}", chain, version);
+    }
+
+    Ok(client)
+}
+
+#[cfg(test)]
+pub mod test_client {
+    use super::Client;
+    use crate::async_db::test_async_db::block;
+    use crate::shutdown::Shutdown;
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use realtps_common::{chain::Chain, db::Block};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Serves a chain of blocks up to `head_block_number`, each timestamped
+    /// with its number, except that each of `stale_blocks` is served once
+    /// first.
+    pub struct FakeClient {
+        chain: Chain,
+        head_block_number: u64,
+        stale_blocks: Mutex<HashMap<u64, Block>>,
+        /// Requests shutdown when the block is fetched.
+        pub shutdown_at: Option<(u64, Shutdown)>,
+        /// The number of blocks fetched.
+        pub num_blocks_fetched: Mutex<usize>,
+    }
+
+    impl FakeClient {
+        pub fn new(chain: Chain, head_block_number: u64, stale_blocks: Vec<Block>) -> FakeClient {
+            let stale_blocks = stale_blocks
+                .into_iter()
+                .map(|block| (block.block_number, block))
+                .collect();
+            FakeClient {
+                chain,
+                head_block_number,
+                stale_blocks: Mutex::new(stale_blocks),
+                shutdown_at: None,
+                num_blocks_fetched: Mutex::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Client for FakeClient {
+        async fn client_version(&self) -> Result<String> {
+            Ok("fake".to_string())
+        }
+
+        async fn get_latest_block_number(&self) -> Result<u64> {
+            Ok(self.head_block_number)
+        }
+
+        async fn get_block(&self, block_number: u64) -> Result<Option<Block>> {
+            if let Some((shutdown_block_number, shutdown)) = &self.shutdown_at {
+                if *shutdown_block_number == block_number {
+                    shutdown.request();
+                }
+            }
+            *self.num_blocks_fetched.lock().expect("poisoned") += 1;
+            let stale_block = self
+                .stale_blocks
+                .lock()
+                .expect("poisoned")
+                .remove(&block_number);
+            Ok(stale_block.or_else(|| {
+                (block_number <= self.head_block_number)
+                    .then(|| block(self.chain, block_number, block_number, 1, ""))
+            }))
+        }
+    }
+}
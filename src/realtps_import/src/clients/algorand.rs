@@ -1,27 +1,102 @@
 use crate::client::Client;
-use algonaut::{algod::v2::Algod, indexer::v2::Indexer};
-use anyhow::Result;
+use algonaut::algod::v2::Algod;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use hex::ToHex;
+use data_encoding::{BASE32_NOPAD, BASE64};
+use log::debug;
 use realtps_common::{chain::Chain, db::Block};
+use std::collections::HashSet;
+
+/// Algod gives block hashes this prefix.
+const ALGOD_BLOCK_HASH_PREFIX: &str = "blk-";
 
 pub struct AlgorandClient {
+    client: reqwest::Client,
     algod: Algod,
-    indexer: Indexer,
+    algod_url: String,
+    /// For blocks algod no longer has, if it isn't an archival node.
+    indexer_url: Option<String>,
 }
 
 impl AlgorandClient {
+    /// Takes an algod URL, optionally followed by `;` and an indexer URL.
     pub fn new(urls: &str) -> Result<Self> {
-        let urls: Vec<&str> = urls.split(';').collect();
-        let algod_url = urls.first().expect("algorand algod url");
-        let indexer_url = urls.get(1).expect("algorand indexer url");
+        let mut urls = urls.split(';');
+        let algod_url = urls.next().expect("algorand algod url");
+        let indexer_url = urls
+            .next()
+            .filter(|url| !url.is_empty())
+            .map(|url| url.trim_end_matches('/').to_string());
         Ok(Self {
+            client: reqwest::Client::new(),
             algod: Algod::with_headers(algod_url, vec![])?,
-            indexer: Indexer::with_headers(indexer_url, vec![])?,
+            algod_url: algod_url.trim_end_matches('/').to_string(),
+            indexer_url,
         })
     }
 }
 
+#[derive(serde::Deserialize)]
+struct AlgodBlockResponse {
+    block: AlgodBlock,
+}
+
+#[derive(serde::Deserialize)]
+struct AlgodBlock {
+    ts: u64,
+    /// Missing for the genesis block.
+    #[serde(default)]
+    prev: Option<String>,
+    #[serde(default)]
+    txns: Vec<AlgodTransaction>,
+}
+
+/// A transaction in a block, with the inner transactions it issued.
+#[derive(serde::Deserialize)]
+struct AlgodTransaction {
+    #[serde(default)]
+    txn: AlgodTxn,
+    #[serde(default)]
+    dt: Option<AlgodEvalDelta>,
+}
+
+#[derive(serde::Deserialize, Default)]
+struct AlgodTxn {
+    /// The ID of the atomic group the transaction was sent in, if any.
+    #[serde(default)]
+    grp: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct AlgodEvalDelta {
+    #[serde(default)]
+    itx: Vec<AlgodTransaction>,
+}
+
+#[derive(serde::Deserialize)]
+struct AlgodBlockHashResponse {
+    #[serde(rename = "blockHash")]
+    block_hash: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct IndexerBlock {
+    timestamp: u64,
+    previous_block_hash: String,
+    #[serde(default)]
+    transactions: Vec<IndexerTransaction>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct IndexerTransaction {
+    #[serde(default)]
+    group: Option<String>,
+    #[serde(default)]
+    inner_txns: Vec<IndexerTransaction>,
+}
+
 #[async_trait]
 impl Client for AlgorandClient {
     async fn client_version(&self) -> Result<String> {
@@ -34,35 +109,184 @@ impl Client for AlgorandClient {
         Ok(status.last_round)
     }
 
+    // Algorand has instant finality.
+    async fn get_finalized_block_number(&self) -> Result<Option<u64>> {
+        self.get_latest_block_number().await.map(Some)
+    }
+
+    // Top-level transactions are counted as transactions, including each
+    // member of an atomic group, and the atomic groups and the inner
+    // transactions issued by application calls are counted separately.
     async fn get_block(&self, block_number: u64) -> Result<Option<Block>> {
-        let block = self
-            .algod
-            .block_with_certificate(block_number.into())
-            .await?;
+        let url = format!("{}/v2/blocks/{}?format=json", self.algod_url, block_number);
+        let resp = self.client.get(url).send().await?;
+        // Algod answers 404 for blocks it has pruned, or doesn't have yet.
+        // Other errors are algod's own, and the indexer can't stand in for
+        // them.
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            match &self.indexer_url {
+                Some(indexer_url) => {
+                    debug!(
+                        "algod returned {} for block {}, trying the indexer for chain {}",
+                        resp.status(),
+                        block_number,
+                        Chain::Algorand
+                    );
+                    return self.get_indexer_block(indexer_url, block_number).await;
+                }
+                None => return Ok(None),
+            }
+        }
+        let block = resp
+            .error_for_status()?
+            .json::<AlgodBlockResponse>()
+            .await?
+            .block;
 
-        let indexer_block = self.indexer.block(block_number.into()).await?;
+        let url = format!("{}/v2/blocks/{}/hash", self.algod_url, block_number);
+        let resp = self.client.get(url).send().await?.error_for_status()?;
+        let hash_response: AlgodBlockHashResponse = resp.json().await?;
+
+        let parent_hash = match &block.prev {
+            Some(prev) => base32_hash_to_hex(prev)?,
+            None => hex::encode([0u8; 32]),
+        };
 
         Ok(Some(Block {
             chain: Chain::Algorand,
             block_number,
-            prev_block_number: if block_number > 0 {
-                Some(block_number - 1)
-            } else {
-                None
-            },
-            timestamp: indexer_block.timestamp,
-            num_txs: indexer_block.transactions.len() as u64,
-            hash: block.hash().encode_hex(),
-            parent_hash: indexer_block.previous_block_hash.encode_hex(),
+            prev_block_number: prev_block_number(block_number),
+            timestamp: block.ts,
+            num_txs: u64::try_from(block.txns.len())?,
+            hash: base32_hash_to_hex(&hash_response.block_hash)?,
+            parent_hash,
+            gas_used: None,
+            num_ops: None,
+            num_system_txs: None,
+            num_failed_txs: None,
+            num_inner_txs: Some(u64::try_from(count_algod_inner_txns(&block.txns))?),
+            num_tx_groups: Some(u64::try_from(count_groups(
+                block.txns.iter().map(|txn| &txn.txn.grp),
+            ))?),
         }))
     }
 }
 
+impl AlgorandClient {
+    /// Reads a block from the indexer. The indexer doesn't give a block's own
+    /// hash, so it is taken from the next block. That fails for the head
+    /// block, which algod still has, so only pruned blocks are read here.
+    async fn get_indexer_block(
+        &self,
+        indexer_url: &str,
+        block_number: u64,
+    ) -> Result<Option<Block>> {
+        let block = match self
+            .get_indexer_block_json(indexer_url, block_number)
+            .await?
+        {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        let next_block_number = block_number.checked_add(1).expect("overflow");
+        let next_block = self
+            .get_indexer_block_json(indexer_url, next_block_number)
+            .await?
+            .ok_or_else(|| {
+                anyhow!(
+                    "indexer has no block {} to hash block {} for chain {}",
+                    next_block_number,
+                    block_number,
+                    Chain::Algorand
+                )
+            })?;
+
+        Ok(Some(Block {
+            chain: Chain::Algorand,
+            block_number,
+            prev_block_number: prev_block_number(block_number),
+            timestamp: block.timestamp,
+            num_txs: u64::try_from(block.transactions.len())?,
+            hash: base64_hash_to_hex(&next_block.previous_block_hash)?,
+            parent_hash: base64_hash_to_hex(&block.previous_block_hash)?,
+            gas_used: None,
+            num_ops: None,
+            num_system_txs: None,
+            num_failed_txs: None,
+            num_inner_txs: Some(u64::try_from(count_indexer_inner_txns(
+                &block.transactions,
+            ))?),
+            num_tx_groups: Some(u64::try_from(count_groups(
+                block.transactions.iter().map(|txn| &txn.group),
+            ))?),
+        }))
+    }
+
+    /// Reads a block from the indexer, or `None` if it doesn't have it.
+    async fn get_indexer_block_json(
+        &self,
+        indexer_url: &str,
+        block_number: u64,
+    ) -> Result<Option<IndexerBlock>> {
+        let url = format!("{}/v2/blocks/{}", indexer_url, block_number);
+        let resp = self.client.get(url).send().await?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(resp.error_for_status()?.json().await?))
+    }
+}
+
+fn prev_block_number(block_number: u64) -> Option<u64> {
+    if block_number > 0 {
+        Some(block_number - 1)
+    } else {
+        None
+    }
+}
+
+/// Counts inner transactions at every depth, since inner application calls
+/// can issue their own.
+fn count_algod_inner_txns(txns: &[AlgodTransaction]) -> usize {
+    txns.iter()
+        .filter_map(|txn| txn.dt.as_ref())
+        .map(|dt| dt.itx.len() + count_algod_inner_txns(&dt.itx))
+        .sum()
+}
+
+fn count_indexer_inner_txns(txns: &[IndexerTransaction]) -> usize {
+    txns.iter()
+        .map(|txn| txn.inner_txns.len() + count_indexer_inner_txns(&txn.inner_txns))
+        .sum()
+}
+
+/// Counts the distinct atomic groups among a block's top-level transactions.
+fn count_groups<'a>(groups: impl Iterator<Item = &'a Option<String>>) -> usize {
+    groups.flatten().collect::<HashSet<_>>().len()
+}
+
+/// Algod hashes are base32, and stored hashes are hex.
+fn base32_hash_to_hex(hash: &str) -> Result<String> {
+    let hash = hash.trim_start_matches(ALGOD_BLOCK_HASH_PREFIX);
+    let hash = BASE32_NOPAD
+        .decode(hash.as_bytes())
+        .map_err(|e| anyhow!("bad algorand block hash {}: {}", hash, e))?;
+    Ok(hex::encode(hash))
+}
+
+/// Indexer hashes are base64, and stored hashes are hex.
+fn base64_hash_to_hex(hash: &str) -> Result<String> {
+    let hash = BASE64
+        .decode(hash.as_bytes())
+        .map_err(|e| anyhow!("bad algorand block hash {}: {}", hash, e))?;
+    Ok(hex::encode(hash))
+}
+
 #[cfg(test)]
 mod test_algorand {
     use anyhow::Result;
 
-    use super::{AlgorandClient, Client};
+    use super::{count_algod_inner_txns, count_groups, AlgodBlockResponse, AlgorandClient, Client};
 
     fn create_client() -> Result<AlgorandClient> {
         AlgorandClient::new("https://mainnet-api.algonode.cloud;https://mainnet-idx.algonode.cloud")
@@ -94,4 +318,55 @@ mod test_algorand {
         println!("block: {block:?}");
         Ok(())
     }
+
+    #[tokio::test]
+    async fn get_block_without_indexer() -> Result<(), anyhow::Error> {
+        let client = AlgorandClient::new("https://mainnet-api.algonode.cloud")?;
+        let latest_block_number = client.get_latest_block_number().await?;
+        let block = client.get_block(latest_block_number).await?;
+        println!("block: {block:?}");
+        assert!(block.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn algod_and_indexer_blocks_agree() -> Result<(), anyhow::Error> {
+        let client = create_client()?;
+        let indexer_url = client.indexer_url.clone().expect("indexer url");
+        let block_number = client.get_latest_block_number().await? - 10;
+        let algod_block = client.get_block(block_number).await?.expect("block");
+        let indexer_block = client
+            .get_indexer_block(&indexer_url, block_number)
+            .await?
+            .expect("block");
+        assert_eq!(algod_block.hash, indexer_block.hash);
+        assert_eq!(algod_block.parent_hash, indexer_block.parent_hash);
+        assert_eq!(algod_block.num_txs, indexer_block.num_txs);
+        assert_eq!(algod_block.num_inner_txs, indexer_block.num_inner_txs);
+        assert_eq!(algod_block.num_tx_groups, indexer_block.num_tx_groups);
+        Ok(())
+    }
+
+    #[test]
+    fn count_algod_groups_and_inner_txns() -> Result<(), anyhow::Error> {
+        let json = r#"{
+            "block": {
+                "ts": 1700000000,
+                "prev": "blk-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA",
+                "txns": [
+                    { "txn": { "type": "pay", "grp": "Z3JvdXAx" } },
+                    { "txn": { "type": "appl", "grp": "Z3JvdXAx" },
+                      "dt": { "itx": [ { "txn": { "type": "pay" } } ] } },
+                    { "txn": { "type": "pay" } },
+                    { "txn": { "type": "axfer", "grp": "Z3JvdXAy" } },
+                    { "txn": { "type": "axfer", "grp": "Z3JvdXAy" } }
+                ]
+            }
+        }"#;
+        let block = serde_json::from_str::<AlgodBlockResponse>(json)?.block;
+        assert_eq!(block.txns.len(), 5);
+        assert_eq!(count_algod_inner_txns(&block.txns), 1);
+        assert_eq!(count_groups(block.txns.iter().map(|txn| &txn.txn.grp)), 2);
+        Ok(())
+    }
 }
@@ -77,6 +77,12 @@ impl Client for EsploraClient {
             num_txs: u64::from(block.tx_count),
             hash: block.id,
             parent_hash: block.previousblockhash,
+            gas_used: None,
+            num_ops: None,
+            num_system_txs: None,
+            num_failed_txs: None,
+            num_inner_txs: None,
+            num_tx_groups: None,
         };
 
         Ok(Some(block))
@@ -1,3 +1,4 @@
+use crate::classify::{self, TxCounts};
 use crate::client::Client;
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
@@ -8,13 +9,19 @@ use realtps_common::{chain::Chain, db::Block};
 pub struct EthersClient {
     chain: Chain,
     provider: Provider<Http>,
+    /// The EIP-2718 types of the chain's system transactions, if it has any.
+    system_tx_types: Vec<u64>,
 }
 
 impl EthersClient {
-    pub fn new(chain: Chain, url: &str) -> Result<Self> {
+    pub fn new(chain: Chain, url: &str, system_tx_types: Vec<u64>) -> Result<Self> {
         let provider = Provider::<Http>::try_from(url)?;
 
-        Ok(EthersClient { chain, provider })
+        Ok(EthersClient {
+            chain,
+            provider,
+            system_tx_types,
+        })
     }
 }
 
@@ -28,25 +35,71 @@ impl Client for EthersClient {
         Ok(self.provider.get_block_number().await?.as_u64())
     }
 
+    async fn get_finalized_block_number(&self) -> Result<Option<u64>> {
+        let block: Option<ethers::prelude::Block<H256>> = self
+            .provider
+            .request("eth_getBlockByNumber", ("finalized", false))
+            .await?;
+
+        Ok(block
+            .and_then(|block| block.number)
+            .map(|number| number.as_u64()))
+    }
+
     async fn get_block(&self, block_number: u64) -> Result<Option<Block>> {
+        // System transactions can only be told apart by fetching whole
+        // transactions instead of their hashes
+        if !self.system_tx_types.is_empty() {
+            return match self.provider.get_block_with_txs(block_number).await? {
+                Some(block) => {
+                    let tx_counts = block
+                        .transactions
+                        .iter()
+                        .map(|tx| {
+                            let tx_type = tx.transaction_type.map(|tx_type| tx_type.as_u64());
+                            classify::ethers_tx_kind(&self.system_tx_types, tx_type)
+                        })
+                        .collect();
+                    ethers_block_to_block(self.chain, block, Some(tx_counts)).map(Some)
+                }
+                None => Ok(None),
+            };
+        }
+
         if let Some(block) = self.provider.get_block(block_number).await? {
             // I like this `map` <3
-            ethers_block_to_block(self.chain, block).map(Some)
+            ethers_block_to_block(self.chain, block, None).map(Some)
         } else {
             Ok(None)
         }
     }
 }
 
-fn ethers_block_to_block(chain: Chain, block: ethers::prelude::Block<H256>) -> Result<Block> {
+/// Converts a block with either transaction hashes or whole transactions,
+/// with `tx_counts` for chains whose transactions are classified.
+fn ethers_block_to_block<TX>(
+    chain: Chain,
+    block: ethers::prelude::Block<TX>,
+    tx_counts: Option<TxCounts>,
+) -> Result<Block> {
     let block_number = block.number.expect("block number").as_u64();
+    let (num_txs, num_system_txs) = match tx_counts {
+        Some(tx_counts) => (tx_counts.user, Some(tx_counts.system)),
+        None => (u64::try_from(block.transactions.len())?, None),
+    };
     Ok(Block {
         chain,
         block_number,
         prev_block_number: block_number.checked_sub(1),
         timestamp: u64::try_from(block.timestamp).map_err(|e| anyhow!("{}", e))?,
-        num_txs: u64::try_from(block.transactions.len())?,
+        num_txs,
         hash: block.hash.expect("hash").encode_hex(),
         parent_hash: block.parent_hash.encode_hex(),
+        gas_used: Some(u64::try_from(block.gas_used).map_err(|e| anyhow!("{}", e))?),
+        num_ops: None,
+        num_system_txs,
+        num_failed_txs: None,
+        num_inner_txs: None,
+        num_tx_groups: None,
     })
 }
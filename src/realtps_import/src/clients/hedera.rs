@@ -79,6 +79,12 @@ impl Client for HederaClient {
             num_txs: block.count as u64,
             hash: block.hash,
             parent_hash: block.previous_hash,
+            gas_used: None,
+            num_ops: None,
+            num_system_txs: None,
+            num_failed_txs: None,
+            num_inner_txs: None,
+            num_tx_groups: None,
         }))
     }
 }
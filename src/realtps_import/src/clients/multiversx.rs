@@ -63,6 +63,11 @@ impl Client for MultiversXClient {
         }
     }
 
+    // `get_latest_block_number` already reports `erd_highest_final_nonce`.
+    async fn get_finalized_block_number(&self) -> Result<Option<u64>> {
+        self.get_latest_block_number().await.map(Some)
+    }
+
     async fn get_block(&self, block_number: u64) -> Result<Option<Block>> {
         let url = format!("{}/hyperblock/by-nonce/{}", self.url, block_number);
         let resp = self.client.get(url).send().await?;
@@ -130,6 +135,12 @@ impl Client for MultiversXClient {
                     num_txs,
                     hash,
                     parent_hash,
+                    gas_used: None,
+                    num_ops: None,
+                    num_system_txs: None,
+                    num_failed_txs: None,
+                    num_inner_txs: None,
+                    num_tx_groups: None,
                 }))
             }
         }
@@ -74,5 +74,11 @@ fn near_block_to_block(block: BlockView, block_number: u64, num_txs: u64) -> Res
         num_txs,
         hash: block.header.hash.to_string(),
         parent_hash: block.header.prev_hash.to_string(),
+        gas_used: None,
+        num_ops: None,
+        num_system_txs: None,
+        num_failed_txs: None,
+        num_inner_txs: None,
+        num_tx_groups: None,
     })
 }
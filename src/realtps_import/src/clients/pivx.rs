@@ -76,6 +76,12 @@ impl Client for PivxClient {
             num_txs: block_info.tx.len() as u64,
             hash: block_info.hash,
             parent_hash: block_info.previousblockhash,
+            gas_used: None,
+            num_ops: None,
+            num_system_txs: None,
+            num_failed_txs: None,
+            num_inner_txs: None,
+            num_tx_groups: None,
         }))
     }
 }
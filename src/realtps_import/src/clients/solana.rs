@@ -1,11 +1,14 @@
+use crate::classify::{self, TxCounts};
 use crate::client::Client;
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
 use log::{debug, trace};
 use realtps_common::{chain::Chain, db::Block};
 use solana_client::{rpc_client::RpcClient, rpc_config::RpcBlockConfig};
-use solana_sdk::{commitment_config::CommitmentConfig, message::VersionedMessage};
-use solana_transaction_status::{TransactionDetails, UiConfirmedBlock, UiTransactionEncoding};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, sysvar};
+use solana_transaction_status::{
+    EncodedTransaction, TransactionDetails, UiConfirmedBlock, UiTransactionEncoding,
+};
 use std::sync::Arc;
 use tokio::task;
 
@@ -37,6 +40,16 @@ impl Client for SolanaClient {
         Ok(slot)
     }
 
+    async fn get_finalized_block_number(&self) -> Result<Option<u64>> {
+        let client = self.client.clone();
+        let slot = task::spawn_blocking(move || {
+            client.get_slot_with_commitment(CommitmentConfig::finalized())
+        })
+        .await??;
+
+        Ok(Some(slot))
+    }
+
     async fn get_block(&self, block_number: u64) -> Result<Option<Block>> {
         // todo: error handling with return missing block
         // `ClientResult<EncodedConfirmedBlock>`
@@ -44,7 +57,9 @@ impl Client for SolanaClient {
         let client = self.client.clone();
         let config = RpcBlockConfig {
             encoding: Some(UiTransactionEncoding::Base64),
-            transaction_details: Some(TransactionDetails::Full),
+            // Enough to tell votes and failures apart,
+            // without instructions or log messages
+            transaction_details: Some(TransactionDetails::Accounts),
             rewards: Some(false),
             commitment: Some(CommitmentConfig::finalized()),
             max_supported_transaction_version: Some(0),
@@ -59,67 +74,99 @@ impl Client for SolanaClient {
 }
 
 fn solana_block_to_block(block: UiConfirmedBlock, slot_number: u64) -> Result<Block> {
-    fn calc_user_txs(block: &UiConfirmedBlock) -> u64 {
-        let mut num_user_txs = 0;
-
-        if let Some(block_txs) = &block.transactions {
-            for tx_status in block_txs {
-                let tx = tx_status.transaction.decode().unwrap();
-                trace!("tx_meta: {:#?}", tx_status.meta.as_ref().unwrap());
-                trace!("tx: {:#?}", tx);
-
-                let account_keys = match &tx.message {
-                    VersionedMessage::Legacy(message) => &message.account_keys,
-                    VersionedMessage::V0(message) => &message.account_keys,
-                };
-
-                let mut num_vote_instrs = 0;
-                for instr in tx.message.instructions() {
-                    let program_id_index = instr.program_id_index;
-                    let program_id = account_keys[usize::from(program_id_index)];
-
-                    if program_id == solana_sdk::vote::program::id() {
-                        num_vote_instrs += 1;
-                        trace!("found vote instruction");
-                    } else {
-                        trace!("non-vote instruction");
-                    }
-                }
-                if num_vote_instrs == tx.message.instructions().len() {
-                    trace!("it's a vote transaction");
-                } else {
-                    // This doesn't look like a vote transaction
-                    trace!("it's a non-vote transaction");
-                    num_user_txs += 1;
-                }
-            }
-
-            let vote_txs = block_txs
-                .len()
-                .checked_sub(num_user_txs)
-                .expect("underflow");
-
-            debug!("solana total txs: {}", block_txs.len());
-            debug!("solana user txs: {}", num_user_txs);
-            debug!("solana vote txs: {}", vote_txs);
-        } else {
-            debug!("solana total txs: None");
-        }
-
-        u64::try_from(num_user_txs).expect("u64")
-    }
+    let tx_counts = count_txs(&block, slot_number)?;
 
     Ok(Block {
         chain: Chain::Solana,
         block_number: slot_number,
-        prev_block_number: Some(block.parent_slot),
+        // The genesis slot is its own parent
+        prev_block_number: if slot_number > 0 {
+            Some(block.parent_slot)
+        } else {
+            None
+        },
         timestamp: u64::try_from(
             block
                 .block_time
                 .ok_or_else(|| anyhow!("block time unavailable for solana slot {}", slot_number))?,
         )?,
-        num_txs: calc_user_txs(&block),
+        num_txs: tx_counts.user,
         hash: block.blockhash,
         parent_hash: block.previous_blockhash,
+        gas_used: None,
+        num_ops: None,
+        num_system_txs: Some(tx_counts.system),
+        num_failed_txs: Some(tx_counts.failed),
+        num_inner_txs: None,
+        num_tx_groups: None,
     })
 }
+
+/// Counts a block's transactions, fetched with only their accounts and
+/// status, as votes or user transactions, and whether they failed.
+fn count_txs(block: &UiConfirmedBlock, slot_number: u64) -> Result<TxCounts> {
+    let mut tx_counts = TxCounts::default();
+
+    let block_txs = match &block.transactions {
+        Some(block_txs) => block_txs,
+        None => {
+            debug!("solana total txs: None");
+            return Ok(tx_counts);
+        }
+    };
+
+    for tx_status in block_txs {
+        let accounts = match &tx_status.transaction {
+            EncodedTransaction::Accounts(accounts) => accounts,
+            _ => bail!(
+                "transaction without an account list in solana slot {}",
+                slot_number
+            ),
+        };
+        let meta = tx_status.meta.as_ref().ok_or_else(|| {
+            anyhow!(
+                "transaction without status meta in solana slot {}",
+                slot_number
+            )
+        })?;
+        trace!("tx_meta: {:#?}", meta);
+        trace!("tx accounts: {:#?}", accounts);
+
+        // Without the instructions, the programs they call are among the
+        // read-only, unsigned accounts. The rest of those are the accounts
+        // instructions read, which for vote instructions are only sysvars.
+        let mut program_ids = vec![];
+        for account in &accounts.account_keys {
+            if account.writable || account.signer {
+                continue;
+            }
+            let pubkey: Pubkey = account.pubkey.parse().map_err(|e| {
+                anyhow!(
+                    "bad account {} in solana slot {}: {}",
+                    account.pubkey,
+                    slot_number,
+                    e
+                )
+            })?;
+            if !sysvar::is_sysvar_id(&pubkey) {
+                program_ids.push(pubkey);
+            }
+        }
+        let kind = classify::solana_tx_kind(&program_ids, &solana_sdk::vote::program::id());
+        trace!("it's a {:?} transaction", kind);
+
+        if meta.err.is_some() {
+            trace!("it failed");
+            tx_counts.add_failed(kind);
+        } else {
+            tx_counts.add(kind);
+        }
+    }
+
+    debug!("solana total txs: {}", block_txs.len());
+    debug!("solana user txs: {}", tx_counts.user);
+    debug!("solana failed user txs: {}", tx_counts.failed);
+    debug!("solana vote txs: {}", tx_counts.system);
+
+    Ok(tx_counts)
+}
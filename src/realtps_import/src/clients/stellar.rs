@@ -45,6 +45,10 @@ impl Client for StellarClient {
         let network_details: StellarNetworkDetailsResponse = resp.json().await?;
         Ok(network_details.history_latest_ledger as u64)
     }
+    // Closed Stellar ledgers are final.
+    async fn get_finalized_block_number(&self) -> Result<Option<u64>> {
+        self.get_latest_block_number().await.map(Some)
+    }
     async fn get_block(&self, block_number: u64) -> Result<Option<Block>> {
         let url = format!("{}/ledgers/{}", &self.url, block_number);
         let resp = self.client.get(url).send().await?;
@@ -66,6 +70,12 @@ impl Client for StellarClient {
             num_txs,
             hash: ledger.hash,
             parent_hash: ledger.prev_hash,
+            gas_used: None,
+            num_ops: Some(u64::from(ledger.operation_count)),
+            num_system_txs: None,
+            num_failed_txs: None,
+            num_inner_txs: None,
+            num_tx_groups: None,
         }))
     }
 }
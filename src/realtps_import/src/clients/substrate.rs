@@ -1,3 +1,4 @@
+use crate::classify::{self, TxCounts};
 use crate::client::Client;
 use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
@@ -57,6 +58,30 @@ impl Client for SubstrateClient {
         Ok(number)
     }
 
+    async fn get_finalized_block_number(&self) -> Result<Option<u64>> {
+        let hash = self
+            .client
+            .call_method("chain_getFinalizedHead", Params::None)
+            .await
+            .map_err(|e| anyhow!("{}", e))?;
+
+        trace!("finalized hash: {:#?}", hash);
+
+        let header = self
+            .client
+            .call_method("chain_getHeader", Params::Array(vec![hash]))
+            .await
+            .map_err(|e| anyhow!("{}", e))?;
+
+        trace!("finalized header: {:#?}", header);
+
+        let number_hex = header.get("number").expect("number").as_str().expect("str");
+        let number = hex_be_to_u32(number_hex)?;
+        let number = u64::from(number);
+
+        Ok(Some(number))
+    }
+
     async fn get_block(&self, block_number: u64) -> Result<Option<Block>> {
         let block_number = u32::try_from(block_number)?;
         let hash = self
@@ -169,7 +194,16 @@ fn substrate_block_to_block(
         .expect("array");
 
     let prev_block_number = block_number.checked_sub(1);
-    let num_txs = u64::try_from(extrinsics.len())?;
+    let tx_counts = extrinsics
+        .iter()
+        .map(|extrinsic| {
+            let extrinsic = extrinsic
+                .as_str()
+                .ok_or_else(|| anyhow!("extrinsic wasn't a string: {:?}", extrinsic))?;
+            let version = extrinsic_version(extrinsic)?;
+            Ok(classify::substrate_extrinsic_kind(version))
+        })
+        .collect::<Result<TxCounts>>()?;
     let parent_hash = header
         .get("parentHash")
         .expect("parentHash")
@@ -182,8 +216,90 @@ fn substrate_block_to_block(
         block_number,
         prev_block_number,
         timestamp,
-        num_txs,
+        num_txs: tx_counts.user,
         hash,
         parent_hash,
+        gas_used: None,
+        num_ops: None,
+        num_system_txs: Some(tx_counts.system),
+        num_failed_txs: None,
+        num_inner_txs: None,
+        num_tx_groups: None,
     })
 }
+
+/// Reads the version byte of a hex-encoded extrinsic, which says whether it
+/// is signed. It follows the extrinsic's SCALE compact length prefix.
+fn extrinsic_version(extrinsic_hex: &str) -> Result<u8> {
+    let bytes = hex::decode(extrinsic_hex.trim_start_matches("0x"))?;
+    let first_byte = *bytes.first().ok_or_else(|| anyhow!("empty extrinsic"))?;
+    // The low two bits give the prefix's length
+    let prefix_len = match first_byte & 0b11 {
+        0b00 => 1,
+        0b01 => 2,
+        0b10 => 4,
+        _ => usize::from(first_byte >> 2) + 5,
+    };
+    let version = bytes
+        .get(prefix_len)
+        .ok_or_else(|| anyhow!("extrinsic ended in its length prefix"))?;
+
+    Ok(*version)
+}
+
+#[cfg(test)]
+mod test_substrate {
+    use super::extrinsic_version;
+    use crate::classify::{self, TxKind};
+
+    #[test]
+    fn signed_extrinsic() -> anyhow::Result<()> {
+        // A one-byte length prefix of 7
+        let version = extrinsic_version("0x1c8400aabbccddeeff")?;
+        assert_eq!(version, 0x84);
+        assert_eq!(classify::substrate_extrinsic_kind(version), TxKind::User);
+        Ok(())
+    }
+
+    #[test]
+    fn bare_extrinsic() -> anyhow::Result<()> {
+        let version = extrinsic_version("0x0c040300")?;
+        assert_eq!(version, 0x04);
+        assert_eq!(classify::substrate_extrinsic_kind(version), TxKind::System);
+        Ok(())
+    }
+
+    #[test]
+    fn general_extrinsic() -> anyhow::Result<()> {
+        for (extrinsic, expected) in [("0x0c440300", 0x44), ("0x0c450300", 0x45)] {
+            let version = extrinsic_version(extrinsic)?;
+            assert_eq!(version, expected);
+            assert_eq!(classify::substrate_extrinsic_kind(version), TxKind::User);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn multi_byte_length_prefixes() -> anyhow::Result<()> {
+        // Two-byte mode, a length of 69
+        assert_eq!(extrinsic_version("0x150184")?, 0x84);
+        // Four-byte mode
+        assert_eq!(extrinsic_version("0x0200010004")?, 0x04);
+        Ok(())
+    }
+
+    #[test]
+    fn big_integer_length_prefix() -> anyhow::Result<()> {
+        // Big-integer mode, with the upper six bits giving four length bytes
+        assert_eq!(extrinsic_version("0x030000000184")?, 0x84);
+        // Five length bytes
+        assert_eq!(extrinsic_version("0x07000000000104")?, 0x04);
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_extrinsic() {
+        assert!(extrinsic_version("0x").is_err());
+        assert!(extrinsic_version("0x0300000001").is_err());
+    }
+}
@@ -1,9 +1,19 @@
 use crate::client::Client;
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use log::warn;
 use realtps_common::{chain::Chain, db::Block};
+use tendermint_rpc::error::ErrorDetail;
 use tendermint_rpc::{Client as TendermintClientTrait, HttpClient};
 
+/// Parts of the errors nodes give for block results they pruned, or never
+/// kept, while still serving the block.
+const RESULTS_UNAVAILABLE_ERRORS: &[&str] = &[
+    "could not find results for height",
+    "is not available, lowest height is",
+    "node is not persisting abci responses",
+];
+
 pub struct TendermintClient {
     chain: Chain,
     client: HttpClient,
@@ -31,19 +41,82 @@ impl Client for TendermintClient {
         Ok(status.sync_info.latest_block_height.value())
     }
 
+    // Tendermint has instant finality.
+    async fn get_finalized_block_number(&self) -> Result<Option<u64>> {
+        self.get_latest_block_number().await.map(Some)
+    }
+
     async fn get_block(&self, block_number: u64) -> Result<Option<Block>> {
         let tendermint_block_height = tendermint::block::Height::try_from(block_number)?;
         let block_response = self.client.block(tendermint_block_height).await?;
+        // Nodes that prune results still serve blocks, so failed txs go
+        // uncounted rather than the block going missing. Other errors are
+        // retried, so a brief outage doesn't leave blocks unclassified.
+        let block_results_response = match self.client.block_results(tendermint_block_height).await
+        {
+            Ok(block_results_response) => Some(block_results_response),
+            Err(e) if is_results_unavailable(&e) => {
+                warn!(
+                    "no results for block {}, not counting failed txs for chain {}: {}",
+                    block_number, self.chain, e
+                );
+                None
+            }
+            Err(e) => return Err(e.into()),
+        };
 
-        tendermint_block_to_block(self.chain, block_response, block_number).map(Some)
+        tendermint_block_to_block(
+            self.chain,
+            block_response,
+            block_results_response,
+            block_number,
+        )
+        .map(Some)
+    }
+}
+
+fn is_results_unavailable(error: &tendermint_rpc::Error) -> bool {
+    match error.detail() {
+        ErrorDetail::Response(e) => {
+            let message = e.source.message();
+            let data = e.source.data().unwrap_or_default();
+            RESULTS_UNAVAILABLE_ERRORS
+                .iter()
+                .any(|unavailable| message.contains(unavailable) || data.contains(unavailable))
+        }
+        _ => false,
     }
 }
 
 fn tendermint_block_to_block(
     chain: Chain,
     block_response: tendermint_rpc::endpoint::block::Response,
+    block_results_response: Option<tendermint_rpc::endpoint::block_results::Response>,
     block_number: u64,
 ) -> Result<Block> {
+    let num_txs = u64::try_from(block_response.block.data.iter().count())?;
+    // Transactions that fail in `DeliverTx` are still included in the block
+    let num_failed_txs = block_results_response
+        .map(|block_results_response| {
+            let num_failed_txs = block_results_response
+                .txs_results
+                .unwrap_or_default()
+                .iter()
+                .filter(|tx_result| tx_result.code.is_err())
+                .count();
+            u64::try_from(num_failed_txs)
+        })
+        .transpose()?;
+    let num_successful_txs = num_txs
+        .checked_sub(num_failed_txs.unwrap_or(0))
+        .ok_or_else(|| {
+            anyhow!(
+                "block {} has more failed txs than txs for chain {}",
+                block_number,
+                chain
+            )
+        })?;
+
     Ok(Block {
         chain,
         block_number,
@@ -52,7 +125,7 @@ fn tendermint_block_to_block(
             tendermint_proto::google::protobuf::Timestamp::from(block_response.block.header.time)
                 .seconds,
         )?,
-        num_txs: u64::try_from(block_response.block.data.iter().count())?,
+        num_txs: num_successful_txs,
         hash: block_response.block_id.hash.to_string(),
         parent_hash: block_response
             .block
@@ -61,5 +134,11 @@ fn tendermint_block_to_block(
             .ok_or_else(|| anyhow!("no previous block id"))?
             .hash
             .to_string(),
+        gas_used: None,
+        num_ops: None,
+        num_system_txs: None,
+        num_failed_txs,
+        num_inner_txs: None,
+        num_tx_groups: None,
     })
 }
@@ -0,0 +1,31 @@
+use crate::calculate::CalculateConfig;
+use crate::classify::ClassifyConfig;
+use crate::import::ImportConfig;
+use anyhow::{Context, Result};
+use realtps_common::chain::Chain;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// The importer's configuration, usually loaded from `rpc_config.toml`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RpcConfig {
+    /// The RPC URL of each chain's node.
+    pub chains: HashMap<Chain, String>,
+    #[serde(default)]
+    pub import: ImportConfig,
+    #[serde(default)]
+    pub calculate: CalculateConfig,
+    #[serde(default)]
+    pub classify: ClassifyConfig,
+}
+
+pub fn load_rpc_config<P: AsRef<Path>>(path: P) -> Result<RpcConfig> {
+    let rpc_config_file = fs::read_to_string(path).context("unable to load RPC configuration")?;
+
+    let rpc_config = toml::from_str::<RpcConfig>(&rpc_config_file)
+        .context("unable to parse RPC configuration")?;
+
+    Ok(rpc_config)
+}
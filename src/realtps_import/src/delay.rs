@@ -1,11 +1,12 @@
-use crate::Chain;
-use crate::Job;
+use crate::jobs::Job;
+use crate::shutdown::Shutdown;
 use anyhow::Result;
-use log::{debug, warn};
+use log::warn;
 use rand::{
     self,
     distributions::{Distribution, Uniform},
 };
+use realtps_common::chain::Chain;
 use std::future::Future;
 use std::pin::Pin;
 use tokio::time::{self, Duration};
@@ -13,16 +14,28 @@ use tokio::time::{self, Duration};
 /// The default rate to request blocks at, in ms.
 const DEFAULT_BLOCK_PACE: u64 = 500;
 
+/// The default rate to request blocks at while backfilling, in ms.
+///
+/// Slower than the live import so the two don't compete for rate limits.
+const DEFAULT_BACKFILL_BLOCK_PACE: u64 = 1000;
+
 /// The default time to wait between imports, in ms.
 const DEFAULT_RESCAN_DELAY: u64 = 30000;
 
+/// The time to wait before retrying a failed job the first time, in ms.
+/// Doubles with each consecutive failure.
+const BASE_JOB_ERROR_DELAY: u64 = 1000;
+
+/// The longest time to wait before retrying a failed job, in ms.
+const MAX_JOB_ERROR_DELAY: u64 = 5 * 60 * 1000;
+
 /// The pace we want to request blocks at, in ms.
 pub fn block_pace(chain: Chain) -> u64 {
     match chain {
         Chain::Arbitrum => 400, // Subsecond block time
         Chain::Bitcoin => 2000,
-        Chain::MultiversX => 1000,   // 6s block time
-        Chain::Optimism => 2000, // Got blocked at 1000ms, unclear what rate they want
+        Chain::MultiversX => 1000, // 6s block time
+        Chain::Optimism => 2000,   // Got blocked at 1000ms, unclear what rate they want
         // Need to go fast to keep up.
         // Solana's RpcClient will use its built in rate limiter when connecting to public nodes.
         Chain::Solana => 0,
@@ -30,14 +43,38 @@ pub fn block_pace(chain: Chain) -> u64 {
     }
 }
 
-/// Wait between imports, in s.
+/// The pace we want to request blocks at while backfilling, in ms.
+pub fn backfill_block_pace(configured_pace: Option<u64>) -> u64 {
+    configured_pace.unwrap_or(DEFAULT_BACKFILL_BLOCK_PACE)
+}
+
+/// Wait before running a job again after it succeeds, in ms.
+pub fn job_delay(job: &Job) -> u64 {
+    match job {
+        Job::Import(chain) => rescan_delay(*chain),
+        // Never runs again
+        Job::Backfill(..) => 0,
+        Job::RepairGaps(_) => 60 * 10 * 1000,
+        Job::Calculate(_) => 5000,
+        Job::Remove(_) => 60 * 60 * 24 * 1000,
+    }
+}
+
+/// Wait before retrying a failed job, in ms, backing off exponentially with
+/// the number of consecutive failures.
+pub fn job_error_delay(consecutive_failures: u32) -> u64 {
+    let doublings = consecutive_failures.saturating_sub(1).min(16);
+    (BASE_JOB_ERROR_DELAY << doublings).min(MAX_JOB_ERROR_DELAY)
+}
+
+/// Wait between imports, in ms.
 ///
 /// This should be somewhat longer than the average block production time (or
 /// perhaps the block production time / 2) to avoid making requests for new
 /// blocks when there are none, but low enough that the block pace can catch up
 /// to new blocks.
-pub async fn rescan_delay(chain: Chain) {
-    let delay_msecs = match chain {
+fn rescan_delay(chain: Chain) -> u64 {
+    match chain {
         Chain::Arbitrum => 5000, // Subsecond block time
         Chain::Bitcoin => 600000,
         Chain::Hedera => 10000,
@@ -47,35 +84,22 @@ pub async fn rescan_delay(chain: Chain) {
         Chain::Polkadot => 7000, // 6s block time, server rate-limited, can't wait too long
         Chain::Solana => 1000,   // Need to go fast to keep up
         _ => DEFAULT_RESCAN_DELAY,
-    };
-
-    debug!("delaying {} ms to rescan chain {}", delay_msecs, chain);
-    delay(delay_msecs).await
+    }
 }
 
-async fn delay(base_ms: u64) {
+pub async fn delay(base_ms: u64) {
     let jitter = Uniform::from(0..10);
     let delay_msecs = base_ms + jitter.sample(&mut rand::thread_rng());
     let delay_time = Duration::from_millis(delay_msecs);
     time::sleep(delay_time).await;
 }
 
-pub async fn job_error_delay(job: &Job) {
-    let msecs = 1000;
-    debug!("delaying {} ms to retry job {:?}", msecs, job);
-    delay(msecs).await;
-}
-
-pub async fn recalculate_delay() {
-    let msecs = 5000;
-    debug!("delaying {} ms before recaclulating", msecs);
-    delay(msecs).await;
-}
-
-pub async fn remove_data_delay() {
-    let msecs = 60 * 60 * 24 * 1000;
-    debug!("delaying {} ms to remove old blocks", msecs);
-    delay(msecs).await;
+/// Like `delay`, but ends early when shutdown is requested.
+pub async fn delay_unless_shutdown(base_ms: u64, shutdown: &Shutdown) {
+    tokio::select! {
+        _ = delay(base_ms) => {},
+        _ = shutdown.requested() => {},
+    }
 }
 
 pub async fn retry_if_err<'caller, F, T>(chain: Chain, f: F) -> Result<T>
@@ -135,3 +159,55 @@ where
         try_num += 1;
     }
 }
+
+#[cfg(test)]
+mod test_delay {
+    use super::{delay_unless_shutdown, job_error_delay};
+    use crate::shutdown::Shutdown;
+    use tokio::time::{self, Duration};
+
+    #[test]
+    fn job_error_delay_backs_off() {
+        assert_eq!(job_error_delay(1), 1000);
+        assert_eq!(job_error_delay(2), 2000);
+        assert_eq!(job_error_delay(3), 4000);
+        assert_eq!(job_error_delay(9), 256 * 1000);
+        // Capped at 5 minutes
+        assert_eq!(job_error_delay(10), 5 * 60 * 1000);
+        assert_eq!(job_error_delay(u32::MAX), 5 * 60 * 1000);
+    }
+
+    #[tokio::test]
+    async fn delay_ends_on_shutdown() {
+        let shutdown = Shutdown::default();
+        let request_shutdown = async {
+            time::sleep(Duration::from_millis(10)).await;
+            shutdown.request();
+        };
+
+        let delay = async {
+            time::timeout(
+                Duration::from_secs(5),
+                delay_unless_shutdown(60 * 1000, &shutdown),
+            )
+            .await
+        };
+        let (delayed, ()) = tokio::join!(delay, request_shutdown);
+
+        assert!(delayed.is_ok());
+    }
+
+    #[tokio::test]
+    async fn delay_ends_after_shutdown() {
+        let shutdown = Shutdown::default();
+        shutdown.request();
+
+        let delayed = time::timeout(
+            Duration::from_secs(5),
+            delay_unless_shutdown(60 * 1000, &shutdown),
+        )
+        .await;
+
+        assert!(delayed.is_ok());
+    }
+}
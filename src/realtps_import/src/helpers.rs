@@ -1,13 +1,10 @@
+use crate::async_db::AsyncDb;
 use crate::client::Client;
 use crate::delay::{retry_if_err, retry_if_none};
 use anyhow::{anyhow, Result};
 use log::debug;
-use realtps_common::{
-    chain::Chain,
-    db::{Block, CalculationLog, Db},
-};
+use realtps_common::{chain::Chain, db::Block};
 use std::sync::Arc;
-use tokio::task;
 
 pub async fn fetch_live_head_block_number(chain: Chain, client: &dyn Client) -> Result<u64> {
     let live_head_block_number =
@@ -21,6 +18,20 @@ pub async fn fetch_live_head_block_number(chain: Chain, client: &dyn Client) ->
     Ok(live_head_block_number)
 }
 
+pub async fn fetch_finalized_head_block_number(chain: Chain, client: &dyn Client) -> Result<u64> {
+    let finalized_head_block_number =
+        retry_if_err(chain, || Box::pin(client.get_finalized_block_number())).await?;
+    let finalized_head_block_number = finalized_head_block_number
+        .ok_or_else(|| anyhow!("no finalized block number for chain {}", chain))?;
+
+    debug!(
+        "finalized head block number for chain {}: {}",
+        chain, finalized_head_block_number
+    );
+
+    Ok(finalized_head_block_number)
+}
+
 pub async fn fetch_block(chain: Chain, client: &dyn Client, block_number: u64) -> Result<Block> {
     debug!("fetching block {} for chain {}", block_number, chain);
 
@@ -34,11 +45,10 @@ pub async fn fetch_block(chain: Chain, client: &dyn Client, block_number: u64) -
 
 pub async fn store_highest_known_block_number(
     chain: Chain,
-    db: &Arc<dyn Db>,
+    db: &Arc<dyn AsyncDb>,
     block_number: u64,
 ) -> Result<()> {
-    let db = db.clone();
-    task::spawn_blocking(move || db.store_highest_block_number(chain, block_number)).await??;
+    db.store_highest_block_number(chain, block_number).await?;
 
     debug!(
         "new highest known block number for chain {}: {}",
@@ -50,11 +60,9 @@ pub async fn store_highest_known_block_number(
 
 pub async fn load_highest_known_block_number(
     chain: Chain,
-    db: &Arc<dyn Db>,
+    db: &Arc<dyn AsyncDb>,
 ) -> Result<Option<u64>> {
-    let db = db.clone();
-    let highest_known_block_number =
-        task::spawn_blocking(move || db.load_highest_block_number(chain)).await??;
+    let highest_known_block_number = db.load_highest_block_number(chain).await?;
 
     debug!(
         "highest known block number for chain {}: {:?}",
@@ -64,43 +72,31 @@ pub async fn load_highest_known_block_number(
     Ok(highest_known_block_number)
 }
 
-pub async fn store_block(db: &Arc<dyn Db>, block: Block) -> Result<()> {
-    let db = db.clone();
-    task::spawn_blocking(move || db.store_block(block)).await??;
-    Ok(())
-}
-
-pub async fn load_block(
+pub async fn store_lowest_known_block_number(
     chain: Chain,
-    db: &Arc<dyn Db>,
+    db: &Arc<dyn AsyncDb>,
     block_number: u64,
-) -> Result<Option<Block>> {
-    let db = db.clone();
-    let block = task::spawn_blocking(move || db.load_block(chain, block_number)).await??;
-    Ok(block)
-}
-
-pub async fn remove_blocks(chain: Chain, db: &Arc<dyn Db>, blocks: Vec<u64>) -> Result<()> {
-    let db = db.clone();
+) -> Result<()> {
+    db.store_lowest_block_number(chain, block_number).await?;
 
-    task::spawn_blocking(move || {
-        for block in blocks {
-            db.remove_block(chain, block)
-                .unwrap_or_else(|_| panic!("error removing block {} for chain {}", block, chain));
-        }
-    })
-    .await?;
+    debug!(
+        "new lowest known block number for chain {}: {}",
+        chain, block_number
+    );
 
     Ok(())
 }
 
-pub async fn write_calculation_log(
+pub async fn load_lowest_known_block_number(
     chain: Chain,
-    db: &Arc<dyn Db>,
-    log: CalculationLog,
-) -> Result<()> {
-    let db = db.clone();
-    task::spawn_blocking(move || db.store_calculation_log(chain, &log)).await??;
+    db: &Arc<dyn AsyncDb>,
+) -> Result<Option<u64>> {
+    let lowest_known_block_number = db.load_lowest_block_number(chain).await?;
 
-    Ok(())
+    debug!(
+        "lowest known block number for chain {}: {:?}",
+        chain, lowest_known_block_number
+    );
+
+    Ok(lowest_known_block_number)
 }
@@ -1,36 +1,76 @@
 #![allow(clippy::all)]
+use crate::async_db::{AsyncDb, BlockWalker};
 use crate::client::Client;
-use crate::delay;
 use crate::helpers::*;
 use crate::pace_setter::PaceSetter;
-use anyhow::{anyhow, Result};
+use crate::shutdown::Shutdown;
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
 use log::{debug, info, warn};
 use realtps_common::{
     chain::Chain,
-    db::{Block, Db},
+    db::{Block, Reorg, SyncCheckpoint},
 };
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
-pub async fn import(chain: Chain, client: &dyn Client, db: &Arc<dyn Db>) -> Result<()> {
-    let res = import_no_rescan_delay(chain, client, db).await;
-
-    match res {
-        Ok(res) => {
-            delay::rescan_delay(chain).await;
-            Ok(res)
-        }
-        Err(e) => {
-            // Delay will be handled by general error handler
-            Err(e)
-        }
-    }
+/// Import settings, from the `[import]` section of the RPC configuration.
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct ImportConfig {
+    /// Chains that only import blocks the network considers final,
+    /// instead of following the tip of the chain.
+    #[serde(default)]
+    pub finalized_chains: HashSet<Chain>,
+    /// The pace to request blocks at while backfilling, in ms.
+    #[serde(default)]
+    pub backfill_block_pace: Option<u64>,
+    /// How many blocks a chain may fall behind the live head before the
+    /// import gives up walking back to the highest known block, and starts a
+    /// new segment at the live head instead. The gap between the segments is
+    /// left to the gap repair job.
+    #[serde(default)]
+    pub max_lag_blocks: HashMap<Chain, u64>,
 }
 
-async fn import_no_rescan_delay(chain: Chain, client: &dyn Client, db: &Arc<dyn Db>) -> Result<()> {
+/// Imports new blocks for the chain, once.
+///
+/// The job scheduler waits between imports, see `delay::job_delay`.
+pub async fn import(
+    chain: Chain,
+    client: &dyn Client,
+    db: &Arc<dyn AsyncDb>,
+    config: &ImportConfig,
+    shutdown: &Shutdown,
+) -> Result<()> {
     info!("beginning import for {}", chain);
 
     let highest_known_block_number = load_highest_known_block_number(chain, db).await?;
-    let live_head_block_number = fetch_live_head_block_number(chain, client).await?;
+
+    // If a previous sync was interrupted, finish that range first.
+    if let Some(checkpoint) = db.load_sync_checkpoint(chain).await? {
+        if Some(checkpoint.highest_known_block_number) == highest_known_block_number {
+            info!(
+                "resuming sync of chain {} at block {}, from {} to {}",
+                chain,
+                checkpoint.next_block_number,
+                checkpoint.live_head_block_number,
+                checkpoint.highest_known_block_number
+            );
+            return sync(chain, client, db, checkpoint, shutdown).await;
+        } else {
+            warn!("discarding stale sync checkpoint for chain {}", chain);
+            db.remove_sync_checkpoint(chain).await?;
+        }
+    }
+
+    // Chains configured to only import final blocks treat the finalized head
+    // as the live head, so blocks that may still be reorged are never stored.
+    let live_head_block_number = if config.finalized_chains.contains(&chain) {
+        fetch_finalized_head_block_number(chain, client).await?
+    } else {
+        fetch_live_head_block_number(chain, client).await?
+    };
 
     // If we've never synced this chain before, then just establish the first
     // few blocks, and the highest_known_block_number, and wait until next time.
@@ -56,41 +96,88 @@ async fn import_no_rescan_delay(chain: Chain, client: &dyn Client, db: &Arc<dyn
         let needed_blocks = live_head_block_number
             .checked_sub(highest_known_block_number)
             .expect("underflow");
+
+        if let Some(max_lag_blocks) = config.max_lag_blocks.get(&chain) {
+            if needed_blocks > *max_lag_blocks {
+                warn!(
+                    "chain {} is {} blocks behind, more than {}. starting a new segment at block {}",
+                    chain, needed_blocks, max_lag_blocks, live_head_block_number
+                );
+                import_head_blocks(chain, client, db, live_head_block_number).await?;
+                // Without a lowest known block the gap repair job couldn't
+                // tell the gap apart from the end of our data.
+                if load_lowest_known_block_number(chain, db).await?.is_none() {
+                    store_lowest_known_block_number(chain, db, highest_known_block_number).await?;
+                }
+                store_highest_known_block_number(chain, db, live_head_block_number).await?;
+                return Ok(());
+            }
+        }
+
         info!(
             "importing at least {} blocks for chain {}",
             needed_blocks, chain
         );
     }
 
-    sync(
+    let checkpoint = SyncCheckpoint {
         chain,
-        client,
-        db,
         highest_known_block_number,
         live_head_block_number,
-    )
-    .await?;
+        next_block_number: live_head_block_number,
+        next_block_hash: None,
+        reorg: None,
+    };
+
+    sync(chain, client, db, checkpoint, shutdown).await?;
 
     Ok(())
 }
 
+/// How many times a block may fail to fit the hash chain, without a block
+/// being verified in between, before giving up on this sync and letting the
+/// job scheduler try again.
+const MAX_HASH_MISMATCHES: u32 = 3;
+
 /// Fetches and stores blocks starting from `live_head_block_number`, working
 /// backwards until it reaches `highest_known_block_number`, accounting for
 /// chain reorgs, and missing blocks from previous imports, and finally storing
 /// a new highest known block number to disk.
+///
+/// Every fetched block must be the parent of the block fetched before it, so
+/// the blocks stored form a single hash chain from the live head down to the
+/// join point. Each block is held back until its parent is fetched and
+/// matches its parent hash, or is already stored. A parent that doesn't match,
+/// e.g. because we are in the middle of a reorg, or got conflicting info from
+/// different nodes behind a load balancer, most likely means the held back
+/// child came from a node that hadn't seen the reorg yet. So the child is
+/// quarantined, and fetched again.
+///
+/// The range being synced is kept in a checkpoint until the sync completes. If
+/// shutdown is requested the sync stops after the block in progress, and the
+/// next import resumes from the checkpoint.
 async fn sync(
     chain: Chain,
     client: &dyn Client,
-    db: &Arc<dyn Db>,
-    highest_known_block_number: u64,
-    live_head_block_number: u64,
+    db: &Arc<dyn AsyncDb>,
+    checkpoint: SyncCheckpoint,
+    shutdown: &Shutdown,
 ) -> Result<()> {
-    // todo: this doesn't check whether the blocks we're receiving have hash
-    // chains that are consistent - we could be in the middle of a reorg, or get
-    // conflicting info from different nodes behind a load balancer. The latter
-    // case could leave us with blocks that aren't actually in the chain.
-
-    let mut block_number = live_head_block_number;
+    let highest_known_block_number = checkpoint.highest_known_block_number;
+    let live_head_block_number = checkpoint.live_head_block_number;
+    let mut block_number = checkpoint.next_block_number;
+    // The hash the next fetched block must have, i.e. the parent hash of the
+    // lowest block fetched so far. There's nothing to check the head against.
+    let mut expected_hash: Option<String> = checkpoint.next_block_hash.clone();
+    // Grows downward as each stored block turns out to be replaced.
+    let mut reorg: Option<Reorg> = checkpoint.reorg.clone();
+
+    db.store_sync_checkpoint(checkpoint).await?;
+
+    // The lowest fetched block, not yet stored because its parent isn't
+    // verified, and the hash it was expected to have.
+    let mut pending: Option<(Block, Option<String>)> = None;
+    let mut hash_mismatches = 0;
     let joined_chain_block_number;
     let joined_chain_block_hash;
 
@@ -98,12 +185,63 @@ async fn sync(
 
     loop {
         let block = fetch_block(chain, client, block_number).await?;
-        let prev_block_number = block.prev_block_number.expect("not genesis block");
-        let prev_block_hash = block.parent_hash.clone();
 
-        store_block(db, block).await?;
+        if let Some(expected_block_hash) = &expected_hash {
+            if block.block_number != block_number || block.hash != *expected_block_hash {
+                warn!(
+                    "block {} for chain {} doesn't fit the hash chain; expected hash: {}; fetched block {} / {}",
+                    block_number, chain, expected_block_hash, block.block_number, block.hash
+                );
+
+                hash_mismatches += 1;
+
+                if hash_mismatches >= MAX_HASH_MISMATCHES {
+                    // The range is probably stale, so don't resume it.
+                    db.remove_sync_checkpoint(chain).await?;
+                    bail!(
+                        "unable to fetch block {} for chain {} consistent with the hash chain",
+                        block_number,
+                        chain
+                    );
+                }
+
+                // Without a held back child, e.g. after fast-forwarding, the
+                // child is stored and trusted, so just fetch again.
+                if let Some((child_block, child_expected_hash)) = pending.take() {
+                    debug!(
+                        "quarantining block {} / {} for chain {}",
+                        child_block.block_number, child_block.hash, chain
+                    );
+                    block_number = child_block.block_number;
+                    expected_hash = child_expected_hash;
+                    db.store_quarantined_block(child_block).await?;
+                }
+
+                pace.wait().await;
+                continue;
+            }
+        }
+
+        // The fetched block matches the held back block's parent hash.
+        if let Some((child_block, _)) = pending.take() {
+            db.store_block(child_block).await?;
+            hash_mismatches = 0;
+        }
+
+        let prev_block_number = match block.prev_block_number {
+            Some(prev_block_number) => prev_block_number,
+            None => {
+                // This is the genesis block, so there's nothing further back
+                // to join. This happens syncing a young chain from scratch.
+                joined_chain_block_number = block.block_number;
+                joined_chain_block_hash = block.hash.clone();
+                db.store_block(block).await?;
+                break;
+            }
+        };
+        let prev_block_hash = block.parent_hash.clone();
 
-        let prev_stored_block = load_block(chain, db, prev_block_number).await?;
+        let prev_stored_block = db.load_block(chain, prev_block_number).await?;
 
         // If we already have the block then we need to decide whether we have
         // completed the import back to the previous highest_known_block_number,
@@ -111,9 +249,14 @@ async fn sync(
         // fetched previous block hash, and whether we already have the previous
         // block from a previous import that failed to complete.
 
-        let block_number_to_fetch_next = if let Some(prev_stored_block) = prev_stored_block {
+        let (block_number_to_fetch_next, hash_to_fetch_next) = if let Some(prev_stored_block) =
+            prev_stored_block
+        {
             let chain_reorg = prev_stored_block.hash != prev_block_hash;
             if !chain_reorg {
+                // The stored parent verifies the block.
+                db.store_block(block).await?;
+
                 if prev_block_number <= highest_known_block_number {
                     // We did it!
                     joined_chain_block_number = prev_block_number;
@@ -125,30 +268,102 @@ async fn sync(
                     // incomplete import. To avoid wasting a lot of time and bandwidth
                     // "fast-forward" through all the blocks we already know.
                     let highest_unknown_block = fast_forward(chain, db, prev_stored_block).await?;
-                    highest_unknown_block
+                    if let Some(highest_unknown_block) = highest_unknown_block {
+                        highest_unknown_block
+                    } else {
+                        // We already have every block back to genesis.
+                        joined_chain_block_number = prev_block_number;
+                        joined_chain_block_hash = prev_block_hash;
+                        break;
+                    }
                 }
             } else {
                 warn!(
                     "reorg of chain {} at block {}; old hash: {}; new hash: {}",
                     chain, prev_block_number, prev_stored_block.hash, prev_block_hash
                 );
+
+                // The same block may be found replaced again after its
+                // child is fetched again, so the depth counts down from the
+                // highest replaced block.
+                let (depth, time) = match &reorg {
+                    Some(reorg) => {
+                        let highest_replaced_block_number =
+                            reorg.fork_block_number + reorg.depth - 1;
+                        (
+                            highest_replaced_block_number - prev_block_number + 1,
+                            reorg.time,
+                        )
+                    }
+                    None => (1, Utc::now()),
+                };
+                let new_reorg = Reorg {
+                    chain,
+                    fork_block_number: prev_block_number,
+                    depth,
+                    old_hash: prev_stored_block.hash.clone(),
+                    new_hash: prev_block_hash.clone(),
+                    time,
+                };
+                // Recorded now, in case the sync doesn't complete.
+                db.store_reorg(new_reorg.clone()).await?;
+                reorg = Some(new_reorg);
+
+                // Keep the replaced block around instead of overwriting it.
+                db.store_orphan_block(prev_stored_block).await?;
+
                 // continue - have wrong version of prev block
-                prev_block_number
+                pending = Some((block, expected_hash.take()));
+                (prev_block_number, prev_block_hash)
             }
         } else {
-            prev_block_number
+            pending = Some((block, expected_hash.take()));
+            (prev_block_number, prev_block_hash)
         };
 
         debug!(
-            "still need block {} for chain {}",
-            block_number_to_fetch_next, chain
+            "still need block {} / {} for chain {}",
+            block_number_to_fetch_next, hash_to_fetch_next, chain
         );
         block_number = block_number_to_fetch_next;
+        expected_hash = Some(hash_to_fetch_next);
+
+        if shutdown.is_requested() {
+            info!(
+                "stopping sync of chain {} for shutdown at block {}",
+                chain, block_number
+            );
+            // The held back block isn't stored, so resume by fetching it again.
+            let (next_block_number, next_block_hash) = match pending {
+                Some((pending_block, pending_expected_hash)) => {
+                    (pending_block.block_number, pending_expected_hash)
+                }
+                None => (block_number, expected_hash),
+            };
+            let checkpoint = SyncCheckpoint {
+                chain,
+                highest_known_block_number,
+                live_head_block_number,
+                next_block_number,
+                next_block_hash,
+                reorg,
+            };
+            db.store_sync_checkpoint(checkpoint).await?;
+            return Ok(());
+        }
 
         pace.wait().await;
     }
 
     store_highest_known_block_number(chain, db, live_head_block_number).await?;
+    db.remove_sync_checkpoint(chain).await?;
+
+    if let Some(reorg) = reorg {
+        info!(
+            "recorded reorg of chain {} at block {} with depth {}",
+            chain, reorg.fork_block_number, reorg.depth
+        );
+    }
 
     info!(
         "completed import of chain {} to block {} / {}",
@@ -161,13 +376,37 @@ async fn sync(
 async fn import_first_blocks(
     chain: Chain,
     client: &dyn Client,
-    db: &Arc<dyn Db>,
+    db: &Arc<dyn AsyncDb>,
     head_block_number: u64,
 ) -> Result<()> {
     info!("importing first blocks for chain {}", chain);
 
+    let lowest_block_number = import_head_blocks(chain, client, db, head_block_number).await?;
+    store_lowest_known_block_number(chain, db, lowest_block_number).await?;
+    store_highest_known_block_number(chain, db, head_block_number).await?;
+
+    info!("completed first import for chain {}", chain);
+
+    Ok(())
+}
+
+/// Stores the head block and its parent, the start of a contiguous segment of
+/// the chain. Returns the lowest stored block number.
+async fn import_head_blocks(
+    chain: Chain,
+    client: &dyn Client,
+    db: &Arc<dyn AsyncDb>,
+    head_block_number: u64,
+) -> Result<u64> {
     let head_block = fetch_block(chain, client, head_block_number).await?;
-    let prev_block_number = head_block.prev_block_number.expect("not genesis block");
+    let prev_block_number = match head_block.prev_block_number {
+        Some(prev_block_number) => prev_block_number,
+        None => {
+            // A freshly started chain with only the genesis block.
+            db.store_block(head_block).await?;
+            return Ok(head_block_number);
+        }
+    };
     let prev_block_hash = head_block.parent_hash.clone();
     let prev_block = fetch_block(chain, client, prev_block_number).await?;
 
@@ -176,18 +415,22 @@ async fn import_first_blocks(
         return Err(anyhow!("first blocks' hashes don't match for {}", chain));
     }
 
-    store_block(db, head_block).await?;
-    store_block(db, prev_block).await?;
-    store_highest_known_block_number(chain, db, head_block_number).await?;
-
-    info!("completed first import for chain {}", chain);
+    db.store_block(head_block).await?;
+    db.store_block(prev_block).await?;
 
-    Ok(())
+    Ok(prev_block_number)
 }
 
 /// Starting from a known good block, fast-forward until we see a block with a
 /// hash mismatch, or that we don't have yet.
-async fn fast_forward(chain: Chain, db: &Arc<dyn Db>, known_block: Block) -> Result<u64> {
+///
+/// Returns the number and expected hash of the next block to fetch, or `None`
+/// if every block back to genesis is known.
+async fn fast_forward(
+    chain: Chain,
+    db: &Arc<dyn AsyncDb>,
+    known_block: Block,
+) -> Result<Option<(u64, String)>> {
     let mut block = known_block;
 
     info!(
@@ -195,26 +438,258 @@ async fn fast_forward(chain: Chain, db: &Arc<dyn Db>, known_block: Block) -> Res
         chain, block.block_number
     );
 
+    let mut stored_blocks = match block.prev_block_number {
+        Some(prev_block_number) => BlockWalker::new(db.as_ref(), chain, prev_block_number),
+        None => {
+            info!("fast-forwarded chain {} to genesis", chain);
+            return Ok(None);
+        }
+    };
+
     let next_block_number_to_sync = loop {
-        let prev_block_number = block.prev_block_number.expect("not genesis block");
+        let prev_block_number = match block.prev_block_number {
+            Some(prev_block_number) => prev_block_number,
+            None => {
+                info!("fast-forwarded chain {} to genesis", chain);
+                return Ok(None);
+            }
+        };
 
-        let prev_block = load_block(chain, db, prev_block_number).await?;
+        // The walker follows the same links, so yields the stored block at
+        // `prev_block_number`, if there is one
+        let prev_block = stored_blocks.next().await?;
 
         if let Some(prev_block) = prev_block {
             if prev_block.hash != block.parent_hash {
-                break prev_block_number;
+                break (prev_block_number, block.parent_hash);
             } else {
                 block = prev_block;
             }
         } else {
-            break prev_block_number;
+            break (prev_block_number, block.parent_hash);
         }
     };
 
     info!(
         "fast-forwarded chain {} to block {}",
-        chain, next_block_number_to_sync
+        chain, next_block_number_to_sync.0
     );
 
-    Ok(next_block_number_to_sync)
+    Ok(Some(next_block_number_to_sync))
+}
+
+#[cfg(test)]
+mod test_import {
+    use super::{import, ImportConfig};
+    use crate::async_db::test_async_db::{block, MemoryDb};
+    use crate::async_db::AsyncDb;
+    use crate::client::test_client::FakeClient;
+    use crate::shutdown::Shutdown;
+    use anyhow::Result;
+    use realtps_common::chain::Chain;
+    use std::sync::Arc;
+
+    // Imported without pausing between blocks
+    const CHAIN: Chain = Chain::Solana;
+
+    /// Stores blocks 0 to 10.
+    fn imported_db() -> Arc<MemoryDb> {
+        let timestamps: Vec<u64> = (0..=10).collect();
+        Arc::new(MemoryDb::with_blocks(CHAIN, 0, &timestamps))
+    }
+
+    /// Stores blocks 0 to 10, with 9 and 10 from a fork that gets replaced.
+    fn forked_db() -> Arc<MemoryDb> {
+        let db = imported_db();
+        db.put_block(block(CHAIN, 9, 9, 1, "x"));
+        let mut block_10 = block(CHAIN, 10, 10, 1, "x");
+        block_10.parent_hash = "9x".to_string();
+        db.put_block(block_10);
+        db
+    }
+
+    async fn stored_hashes(db: &MemoryDb, block_numbers: &[u64]) -> Result<Vec<Option<String>>> {
+        let mut hashes = vec![];
+        for block_number in block_numbers {
+            let block = db.load_block(CHAIN, *block_number).await?;
+            hashes.push(block.map(|block| block.hash));
+        }
+        Ok(hashes)
+    }
+
+    #[tokio::test]
+    async fn import_new_blocks() -> Result<()> {
+        let db = imported_db();
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        let client = FakeClient::new(CHAIN, 13, vec![]);
+
+        import(
+            CHAIN,
+            &client,
+            &async_db,
+            &ImportConfig::default(),
+            &Shutdown::default(),
+        )
+        .await?;
+
+        assert_eq!(
+            stored_hashes(&db, &[11, 12, 13]).await?,
+            [Some("11".into()), Some("12".into()), Some("13".into())]
+        );
+        assert_eq!(db.load_highest_block_number(CHAIN).await?, Some(13));
+        assert!(db.load_sync_checkpoint(CHAIN).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stale_child_quarantined() -> Result<()> {
+        let db = imported_db();
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        // The head first comes from a node on another fork, so its parent
+        // doesn't match, and the head is the block to fetch again
+        let client = FakeClient::new(CHAIN, 13, vec![block(CHAIN, 13, 13, 1, "a")]);
+
+        import(
+            CHAIN,
+            &client,
+            &async_db,
+            &ImportConfig::default(),
+            &Shutdown::default(),
+        )
+        .await?;
+
+        assert_eq!(
+            stored_hashes(&db, &[11, 12, 13]).await?,
+            [Some("11".into()), Some("12".into()), Some("13".into())]
+        );
+        let quarantined_hashes: Vec<String> = db
+            .quarantined_blocks()
+            .into_iter()
+            .map(|block| block.hash)
+            .collect();
+        assert_eq!(quarantined_hashes, ["13a"]);
+        assert_eq!(db.load_highest_block_number(CHAIN).await?, Some(13));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_resumes_at_unverified_block() -> Result<()> {
+        let db = imported_db();
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        let client = FakeClient::new(CHAIN, 13, vec![]);
+        let shutdown = Shutdown::default();
+        shutdown.request();
+
+        import(
+            CHAIN,
+            &client,
+            &async_db,
+            &ImportConfig::default(),
+            &shutdown,
+        )
+        .await?;
+
+        // The head's parent wasn't fetched, so the head isn't stored
+        assert_eq!(stored_hashes(&db, &[13]).await?, [None]);
+        let checkpoint = db.load_sync_checkpoint(CHAIN).await?.expect("checkpoint");
+        assert_eq!(checkpoint.next_block_number, 13);
+        assert_eq!(checkpoint.next_block_hash, None);
+
+        import(
+            CHAIN,
+            &client,
+            &async_db,
+            &ImportConfig::default(),
+            &Shutdown::default(),
+        )
+        .await?;
+
+        assert_eq!(
+            stored_hashes(&db, &[11, 12, 13]).await?,
+            [Some("11".into()), Some("12".into()), Some("13".into())]
+        );
+        assert!(db.load_sync_checkpoint(CHAIN).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reorg_recorded() -> Result<()> {
+        let db = forked_db();
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        let client = FakeClient::new(CHAIN, 12, vec![]);
+
+        import(
+            CHAIN,
+            &client,
+            &async_db,
+            &ImportConfig::default(),
+            &Shutdown::default(),
+        )
+        .await?;
+
+        assert_eq!(
+            stored_hashes(&db, &[8, 9, 10, 11, 12]).await?,
+            [
+                Some("8".into()),
+                Some("9".into()),
+                Some("10".into()),
+                Some("11".into()),
+                Some("12".into())
+            ]
+        );
+        let reorgs = db.load_reorgs(CHAIN).await?;
+        assert_eq!(reorgs.len(), 1);
+        assert_eq!(reorgs[0].fork_block_number, 9);
+        assert_eq!(reorgs[0].depth, 2);
+        assert_eq!(reorgs[0].old_hash, "9x");
+        assert_eq!(reorgs[0].new_hash, "9");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reorg_recorded_before_shutdown() -> Result<()> {
+        let db = forked_db();
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        let shutdown = Shutdown::default();
+        // Block 11 shows that block 10 was replaced
+        let mut client = FakeClient::new(CHAIN, 12, vec![]);
+        client.shutdown_at = Some((11, shutdown.clone()));
+
+        import(
+            CHAIN,
+            &client,
+            &async_db,
+            &ImportConfig::default(),
+            &shutdown,
+        )
+        .await?;
+
+        let reorgs = db.load_reorgs(CHAIN).await?;
+        assert_eq!(reorgs.len(), 1);
+        assert_eq!(reorgs[0].fork_block_number, 10);
+        assert_eq!(reorgs[0].depth, 1);
+        let checkpoint = db.load_sync_checkpoint(CHAIN).await?.expect("checkpoint");
+        assert_eq!(checkpoint.next_block_number, 11);
+        assert_eq!(
+            checkpoint.reorg.map(|reorg| reorg.fork_block_number),
+            Some(10)
+        );
+
+        // Resuming finds block 10 replaced again, and the rest of the reorg
+        client.shutdown_at = None;
+        import(
+            CHAIN,
+            &client,
+            &async_db,
+            &ImportConfig::default(),
+            &Shutdown::default(),
+        )
+        .await?;
+
+        let reorgs = db.load_reorgs(CHAIN).await?;
+        assert_eq!(reorgs.len(), 1);
+        assert_eq!(reorgs[0].fork_block_number, 9);
+        assert_eq!(reorgs[0].depth, 2);
+        Ok(())
+    }
 }
@@ -1,50 +1,174 @@
-use crate::calculate;
+use crate::async_db::AsyncDb;
+use crate::backfill::{self, BackfillTarget};
+use crate::calculate::{self, CalculateConfig};
 use crate::client::Client;
 use crate::delay;
-use crate::import;
+use crate::import::{self, ImportConfig};
 use crate::remove;
+use crate::repair;
+use crate::shutdown::Shutdown;
 use anyhow::{Context, Result};
-use futures::future::FutureExt;
-use futures::stream::{FuturesUnordered, StreamExt};
-use log::{error, info};
-use rand::prelude::*;
-use realtps_common::{chain::Chain, db::Db};
+use chrono::{Duration, Utc};
+use log::{debug, error, info};
+use realtps_common::{
+    chain::Chain,
+    db::{JobState, JobStatus},
+};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use tokio::task;
 
 #[derive(Debug)]
 pub enum Job {
     Import(Chain),
-    Calculate(Vec<Chain>),
-    Remove(Vec<Chain>),
+    Backfill(Chain, BackfillTarget),
+    RepairGaps(Chain),
+    Calculate(Chain),
+    Remove(Chain),
+}
+
+impl Job {
+    /// A name identifying the job in its status.
+    pub fn name(&self) -> String {
+        match self {
+            Job::Import(chain) => format!("import-{}", chain),
+            Job::Backfill(chain, _) => format!("backfill-{}", chain),
+            Job::RepairGaps(chain) => format!("repair-gaps-{}", chain),
+            Job::Calculate(chain) => format!("calculate-{}", chain),
+            Job::Remove(chain) => format!("remove-{}", chain),
+        }
+    }
+
+    pub fn chain(&self) -> Chain {
+        match self {
+            Job::Import(chain)
+            | Job::Backfill(chain, _)
+            | Job::RepairGaps(chain)
+            | Job::Calculate(chain)
+            | Job::Remove(chain) => *chain,
+        }
+    }
 }
 
 pub struct JobRunner {
-    pub db: Arc<dyn Db>,
+    pub db: Arc<dyn AsyncDb>,
     pub clients: HashMap<Chain, Box<dyn Client>>,
+    pub import_config: ImportConfig,
+    pub calculate_config: CalculateConfig,
+    pub shutdown: Shutdown,
+    job_statuses: Mutex<HashMap<String, JobStatus>>,
 }
 
 impl JobRunner {
+    pub async fn new(
+        db: Arc<dyn AsyncDb>,
+        clients: HashMap<Chain, Box<dyn Client>>,
+        import_config: ImportConfig,
+        calculate_config: CalculateConfig,
+    ) -> Result<JobRunner> {
+        // Keep failure counts and last success times across restarts
+        let job_statuses = db
+            .load_job_statuses()
+            .await?
+            .into_iter()
+            .map(|status| (status.job.clone(), status))
+            .collect();
+
+        Ok(JobRunner {
+            db,
+            clients,
+            import_config,
+            calculate_config,
+            shutdown: Shutdown::default(),
+            job_statuses: Mutex::new(job_statuses),
+        })
+    }
+
+    /// Runs the job, then waits until it should run again.
     pub async fn do_job(&self, job: Job) -> Vec<Job> {
+        self.update_job_status(&job, |status| {
+            status.state = JobState::Running;
+            status.next_run = None;
+        })
+        .await;
+
         let r = match job {
             Job::Import(chain) => self.import(chain).await,
-            Job::Calculate(ref chains) => self.calculate(chains.to_vec()).await,
-            Job::Remove(ref chains) => self.remove(chains.to_vec()).await,
+            Job::Backfill(chain, target) => self.backfill(chain, target).await,
+            Job::RepairGaps(chain) => self.repair_gaps(chain).await,
+            Job::Calculate(chain) => self.calculate(chain).await,
+            Job::Remove(chain) => self.remove(chain).await,
         };
 
-        match r {
-            Ok(new_jobs) => new_jobs,
+        let (delay_msecs, new_jobs) = match r {
+            Ok(new_jobs) => {
+                let delay_msecs = delay::job_delay(&job);
+                let state = if new_jobs.is_empty() {
+                    JobState::Finished
+                } else {
+                    JobState::Sleeping
+                };
+                self.update_job_status(&job, |status| {
+                    status.state = state;
+                    status.consecutive_failures = 0;
+                    status.last_success = Some(Utc::now());
+                    status.next_run = next_run(state, delay_msecs);
+                })
+                .await;
+
+                (delay_msecs, new_jobs)
+            }
             Err(e) => {
-                match job {
-                    Job::Import(chain) => print_error(&format!("error running job for {chain}"), &e),
-                    Job::Calculate(_) | Job::Remove(_) => print_error(&format!("error running job {:?}", &job), &e),
-                }
+                print_error(&format!("error running job {}", job.name()), &e);
+
+                let mut delay_msecs = 0;
+                self.update_job_status(&job, |status| {
+                    status.state = JobState::Failing;
+                    status.consecutive_failures = status.consecutive_failures.saturating_add(1);
+                    status.last_error = Some(format!("{:#}", e));
+                    delay_msecs = delay::job_error_delay(status.consecutive_failures);
+                    status.next_run = next_run(status.state, delay_msecs);
+                })
+                .await;
 
-                delay::job_error_delay(&job).await;
-                vec![job]
+                (delay_msecs, vec![job])
             }
+        };
+
+        if !new_jobs.is_empty() {
+            debug!("delaying {} ms to rerun jobs {:?}", delay_msecs, new_jobs);
+            delay::delay_unless_shutdown(delay_msecs, &self.shutdown).await;
+        }
+
+        new_jobs
+    }
+
+    /// Updates the job's status in memory and in the db.
+    ///
+    /// Failing to store the status is logged and otherwise ignored;
+    /// it shouldn't stop the job from running.
+    async fn update_job_status(&self, job: &Job, f: impl FnOnce(&mut JobStatus)) {
+        let name = job.name();
+        let status = {
+            let mut job_statuses = self.job_statuses.lock().expect("poisoned");
+            let status = job_statuses
+                .entry(name.clone())
+                .or_insert_with(|| JobStatus {
+                    job: name,
+                    chain: job.chain(),
+                    state: JobState::Running,
+                    consecutive_failures: 0,
+                    last_success: None,
+                    last_error: None,
+                    next_run: None,
+                });
+            f(status);
+            status.clone()
+        };
+
+        if let Err(e) = self.db.store_job_status(status).await {
+            print_error(&format!("error storing status of job {}", job.name()), &e);
         }
     }
 
@@ -53,62 +177,147 @@ impl JobRunner {
             .clients
             .get(&chain)
             .context(format!("no client for {}", chain))?;
-        import::import(chain, client.as_ref(), &self.db).await?;
+        import::import(
+            chain,
+            client.as_ref(),
+            &self.db,
+            &self.import_config,
+            &self.shutdown,
+        )
+        .await?;
 
         Ok(vec![Job::Import(chain)])
     }
 
-    async fn calculate(&self, chains: Vec<Chain>) -> Result<Vec<Job>> {
-        info!("beginning tps calculation");
+    async fn backfill(&self, chain: Chain, target: BackfillTarget) -> Result<Vec<Job>> {
+        let client = self
+            .clients
+            .get(&chain)
+            .context(format!("no client for {}", chain))?;
+        let block_pace = delay::backfill_block_pace(self.import_config.backfill_block_pace);
+        let retention = self.calculate_config.longest_window();
+        backfill::backfill(
+            chain,
+            client.as_ref(),
+            &self.db,
+            target,
+            block_pace,
+            retention,
+            &self.shutdown,
+        )
+        .await?;
 
-        let start = Instant::now();
+        // Done, no follow-up job
+        Ok(vec![])
+    }
 
-        let mut tasks: FuturesUnordered<_> = chains
-            .iter()
-            .map(|chain| {
-                let chain = *chain;
-                let calc_future = calculate::calculate_for_chain(chain, self.db.clone());
-                let calc_future = task::spawn(calc_future);
-                calc_future.map(move |calcs| (chain, calcs))
-            })
-            .collect();
+    async fn repair_gaps(&self, chain: Chain) -> Result<Vec<Job>> {
+        let client = self
+            .clients
+            .get(&chain)
+            .context(format!("no client for {}", chain))?;
+        let block_pace = delay::backfill_block_pace(self.import_config.backfill_block_pace);
+        let window = self.calculate_config.longest_window();
+        repair::repair_gaps(
+            chain,
+            client.as_ref(),
+            &self.db,
+            block_pace,
+            window,
+            &self.shutdown,
+        )
+        .await?;
 
-        while let Some((chain, calcs)) = tasks.next().await {
-            let calcs = calcs?;
-            match calcs {
-                Ok(calcs) => {
-                    info!("calculated {} tps for chain {}", calcs.tps, calcs.chain);
-                    let db = self.db.clone();
-                    task::spawn_blocking(move || db.store_tps(calcs.chain, calcs.tps)).await??;
-                }
-                Err(e) => print_error(&format!("error calculating for {}", chain), &e),
-            }
+        Ok(vec![Job::RepairGaps(chain)])
+    }
+
+    async fn calculate(&self, chain: Chain) -> Result<Vec<Job>> {
+        info!("beginning tps calculation for chain {}", chain);
+
+        let start = Instant::now();
+
+        let config = self.calculate_config.clone();
+        let calcs = task::spawn(calculate::calculate_for_chain(
+            chain,
+            self.db.clone(),
+            config,
+        ));
+        let calcs = calcs.await??;
+        for window_calcs in calcs.windows {
+            let window = window_calcs.window;
+            info!(
+                "calculated {} tps over {} for chain {}",
+                window_calcs.tps, window, calcs.chain
+            );
+            self.db
+                .store_window_calcs(calcs.chain, window_calcs)
+                .await?;
         }
 
         let end = Instant::now();
         let duration = end - start;
-        info!("calculation took {} s", duration.as_secs());
-
-        delay::recalculate_delay().await;
+        info!(
+            "calculation for chain {} took {} s",
+            chain,
+            duration.as_secs()
+        );
 
-        Ok(vec![Job::Calculate(chains)])
+        Ok(vec![Job::Calculate(chain)])
     }
 
-    async fn remove(&self, chains: Vec<Chain>) -> Result<Vec<Job>> {
-        info!("removing old data");
+    async fn remove(&self, chain: Chain) -> Result<Vec<Job>> {
+        info!("removing old data for chain {}", chain);
 
-        let mut rng = rand::thread_rng();
-        let mut chains = chains;
-        chains.shuffle(&mut rng);
+        let retention = self.calculate_config.longest_window();
+        remove::remove_old_data_for_chain(chain, self.db.clone(), retention).await?;
 
-        for chain in &chains {
-            remove::remove_old_data_for_chain(*chain, self.db.clone()).await?;
-        }
+        Ok(vec![Job::Remove(chain)])
+    }
+}
+
+fn next_run(state: JobState, delay_msecs: u64) -> Option<chrono::DateTime<Utc>> {
+    match state {
+        JobState::Finished => None,
+        _ => Some(Utc::now() + Duration::milliseconds(delay_msecs as i64)),
+    }
+}
+
+/// Prints the status of every job that has run to stdout.
+pub async fn print_job_statuses(db: &dyn AsyncDb) -> Result<()> {
+    let statuses = db.load_job_statuses().await?;
+    if statuses.is_empty() {
+        println!("no jobs have run");
+        return Ok(());
+    }
 
-        delay::remove_data_delay().await;
+    let fmt_time = |time: Option<chrono::DateTime<Utc>>| {
+        time.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "-".to_string())
+    };
 
-        Ok(vec![Job::Remove(chains)])
+    println!(
+        "{:<28} {:<9} {:>8} {:<19} {:<19} last error",
+        "job", "state", "failures", "last success", "next run"
+    );
+    for status in statuses {
+        let state = match status.state {
+            JobState::Running => "running",
+            JobState::Sleeping => "sleeping",
+            JobState::Failing => "failing",
+            JobState::Finished => "finished",
+        };
+        println!(
+            "{:<28} {:<9} {:>8} {:<19} {:<19} {}",
+            status.job,
+            state,
+            status.consecutive_failures,
+            fmt_time(status.last_success),
+            fmt_time(status.next_run),
+            status.last_error.as_deref().unwrap_or("-"),
+        );
     }
+
+    Ok(())
 }
 
 fn print_error(msg: &str, e: &anyhow::Error) {
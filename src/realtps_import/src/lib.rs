@@ -0,0 +1,30 @@
+//! The RealTPS importer.
+//!
+//! This contains the RPC clients that fetch normalized [`Block`]s from each
+//! supported chain, and the engine that imports those blocks into a [`Db`]
+//! and calculates TPS from them. The `realtps_import` binary is a thin
+//! command line interface over this library.
+//!
+//! [`Block`]: realtps_common::db::Block
+//! [`Db`]: realtps_common::db::Db
+
+pub mod async_db;
+pub mod backfill;
+pub mod calculate;
+pub mod classify;
+pub mod client;
+pub mod clients;
+pub mod config;
+mod delay;
+mod helpers;
+pub mod import;
+pub mod jobs;
+mod pace_setter;
+pub mod remove;
+pub mod repair;
+pub mod shutdown;
+pub mod status;
+
+pub use client::{make_all_clients, make_client, Client};
+pub use config::{load_rpc_config, RpcConfig};
+pub use jobs::{Job, JobRunner};
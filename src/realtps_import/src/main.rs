@@ -1,33 +1,21 @@
-use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use client::Client;
-use clients::*;
-use delay::retry_if_err;
-use futures::future::FutureExt;
+use anyhow::{bail, Result};
+use clap::{Args, Parser, Subcommand};
 use futures::stream::{FuturesUnordered, StreamExt};
-use jobs::{Job, JobRunner};
 use log::{error, info};
 use realtps_common::{
-    chain::{Chain, ChainType},
+    chain::Chain,
     db::JsonDb,
+    window::Window,
 };
-use serde::{Deserialize, Serialize};
+use realtps_import::async_db::{AsyncDb, BlockingDb};
+use realtps_import::backfill::{self, BackfillTarget};
+use realtps_import::{jobs, shutdown, status};
+use realtps_import::{load_rpc_config, make_all_clients, Job, JobRunner, RpcConfig};
 use std::collections::HashMap;
-use std::fs;
 use std::path::Path;
 use std::sync::Arc;
 use tempdir::TempDir;
-use tokio::task;
-
-mod calculate;
-mod client;
-mod clients;
-mod delay;
-mod helpers;
-mod import;
-mod jobs;
-mod pace_setter;
-mod remove;
+use tokio::time;
 
 #[derive(Parser, Debug)]
 struct Opts {
@@ -44,11 +32,45 @@ enum Command {
     Import,
     Calculate,
     Remove,
+    /// Import older blocks, beside a running import. Blocks older than the
+    /// longest calculation window would be removed again, so aren't imported
+    Backfill(BackfillOpts),
+    /// Print the status of the importer's jobs
+    Jobs,
+    /// Print a summary of each chain's import health
+    Status,
 }
 
-#[derive(Deserialize, Serialize)]
-struct RpcConfig {
-    chains: HashMap<Chain, String>,
+#[derive(Args, Debug)]
+struct BackfillOpts {
+    /// How far back to import, like `7d`, `12h` or `30m`, at most the longest
+    /// calculation window
+    #[clap(long, value_parser = backfill::parse_since, conflicts_with = "to-block", required_unless_present = "to-block")]
+    since: Option<Window>,
+
+    /// Import back to this block number, or to the start of the longest
+    /// calculation window if that is later
+    #[clap(long)]
+    to_block: Option<u64>,
+}
+
+impl BackfillOpts {
+    fn target(&self, retention: Window) -> Result<BackfillTarget> {
+        match (self.since, self.to_block) {
+            (Some(since), _) => {
+                if since > retention {
+                    bail!(
+                        "can't backfill {}, blocks older than the longest calculation window, {}, are removed",
+                        since,
+                        retention
+                    );
+                }
+                BackfillTarget::since(since)
+            }
+            (None, Some(to_block)) => Ok(BackfillTarget::BlockNumber(to_block)),
+            (None, None) => unreachable!("clap requires one of --since or --to-block"),
+        }
+    }
 }
 
 static RPC_CONFIG_PATH: &str = "rpc_config.toml";
@@ -57,44 +79,107 @@ static LOG_CONFIG_PATH: &str = "log_config.yml";
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = Opts::parse();
+    let cmd = opts.cmd.unwrap_or(Command::Run);
 
-    init_log(LOG_CONFIG_PATH, &opts.cmd)?;
+    init_log(LOG_CONFIG_PATH, &cmd)?;
 
     let rpc_config = load_rpc_config(RPC_CONFIG_PATH)?;
 
-    run(opts, rpc_config).await
+    run(get_chains(opts.chain), cmd, rpc_config).await
 }
 
-async fn run(opts: Opts, rpc_config: RpcConfig) -> Result<()> {
-    let chains = get_chains(opts.chain);
-    let cmd = opts.cmd.unwrap_or(Command::Run);
-
-    let job_runner = match &cmd {
-        Command::Run | Command::Import => {
-            make_job_runner_with_clients(&chains, &rpc_config).await?
+async fn run(chains: Vec<Chain>, cmd: Command, rpc_config: RpcConfig) -> Result<()> {
+    let (job_runner, init_jobs) = match cmd {
+        Command::Run => {
+            let job_runner = make_job_runner_with_clients(&chains, &rpc_config).await?;
+            let init_jobs = import_jobs(&chains)
+                .into_iter()
+                .chain(chains.iter().cloned().map(Job::Calculate))
+                .chain(chains.iter().cloned().map(Job::Remove))
+                .collect();
+            (job_runner, init_jobs)
+        }
+        Command::Import => {
+            let job_runner = make_job_runner_with_clients(&chains, &rpc_config).await?;
+            (job_runner, import_jobs(&chains))
+        }
+        Command::Calculate => {
+            let job_runner = make_job_runner(&rpc_config).await?;
+            (
+                job_runner,
+                chains.iter().cloned().map(Job::Calculate).collect(),
+            )
+        }
+        Command::Remove => {
+            let job_runner = make_job_runner(&rpc_config).await?;
+            (
+                job_runner,
+                chains.iter().cloned().map(Job::Remove).collect(),
+            )
+        }
+        Command::Backfill(opts) => {
+            let target = opts.target(rpc_config.calculate.longest_window())?;
+            let job_runner = make_job_runner_with_clients(&chains, &rpc_config).await?;
+            // Only the backfill, since the import is already running
+            let init_jobs = chains
+                .iter()
+                .map(|chain| Job::Backfill(*chain, target))
+                .collect();
+            (job_runner, init_jobs)
+        }
+        // Reports, printed instead of running jobs
+        Command::Jobs => return jobs::print_job_statuses(&BlockingDb::new(JsonDb)).await,
+        Command::Status => {
+            let clients = make_all_clients(&chains, &rpc_config).await?;
+            let db: Arc<dyn AsyncDb> = Arc::new(BlockingDb::new(JsonDb));
+            let window = rpc_config.calculate.longest_window();
+            return status::print_status(&chains, &clients, &db, window).await;
         }
-        Command::Calculate | Command::Remove => make_job_runner()?,
     };
 
-    let init_jobs = init_jobs(&chains, cmd);
-
     let mut jobs: FuturesUnordered<_> = init_jobs
         .into_iter()
         .map(|job| job_runner.do_job(job))
         .collect();
 
+    let shutdown_signal = shutdown::wait_for_signal();
+    tokio::pin!(shutdown_signal);
+
     loop {
-        let new_jobs = jobs.next().await;
-        if let Some(new_jobs) = new_jobs {
-            for new_job in new_jobs {
-                jobs.push(job_runner.do_job(new_job));
+        tokio::select! {
+            new_jobs = jobs.next() => {
+                if let Some(new_jobs) = new_jobs {
+                    for new_job in new_jobs {
+                        jobs.push(job_runner.do_job(new_job));
+                    }
+                } else {
+                    error!("no more jobs?!");
+                    break;
+                }
+            }
+            signal = &mut shutdown_signal => {
+                signal?;
+                info!("received shutdown signal, no longer scheduling jobs");
+                job_runner.shutdown.request();
+                break;
             }
-        } else {
-            error!("no more jobs?!");
-            break;
         }
     }
 
+    if job_runner.shutdown.is_requested() {
+        // Let in-flight jobs reach a safe point, without scheduling their
+        // follow-up jobs. Blocking db writes that are still running when the
+        // remaining jobs are dropped are waited for by the runtime on exit.
+        let drain_jobs = async { while jobs.next().await.is_some() {} };
+        if time::timeout(shutdown::SHUTDOWN_GRACE, drain_jobs)
+            .await
+            .is_err()
+        {
+            info!("stopped waiting for remaining jobs");
+        }
+        info!("shutdown complete");
+    }
+
     Ok(())
 }
 
@@ -106,16 +191,15 @@ fn get_chains(maybe_chain: Option<Chain>) -> Vec<Chain> {
     }
 }
 
-fn init_log<P: AsRef<Path>>(path: P, job: &Option<Command>) -> Result<()> {
-    let job_name = if let Some(job) = job {
-        match job {
-            Command::Run => "all-jobs",
-            Command::Import => "import",
-            Command::Calculate => "calculate",
-            Command::Remove => "remove",
-        }
-    } else {
-        "all-jobs"
+fn init_log<P: AsRef<Path>>(path: P, cmd: &Command) -> Result<()> {
+    let job_name = match cmd {
+        Command::Run => "all-jobs",
+        Command::Import => "import",
+        Command::Calculate => "calculate",
+        Command::Remove => "remove",
+        Command::Backfill(_) => "backfill",
+        Command::Jobs => "jobs",
+        Command::Status => "status",
     };
 
     let temp_dir = TempDir::new("temp_dir")?;
@@ -135,113 +219,36 @@ fn init_log<P: AsRef<Path>>(path: P, job: &Option<Command>) -> Result<()> {
     Ok(())
 }
 
-fn load_rpc_config<P: AsRef<Path>>(path: P) -> Result<RpcConfig> {
-    let rpc_config_file = fs::read_to_string(path).context("unable to load RPC configuration")?;
-
-    let rpc_config = toml::from_str::<RpcConfig>(&rpc_config_file)
-        .context("unable to parse RPC configuration")?;
-
-    Ok(rpc_config)
+/// Imports new blocks for each chain, and repairs gaps behind them.
+fn import_jobs(chains: &[Chain]) -> Vec<Job> {
+    let import_jobs = chains.iter().cloned().map(Job::Import);
+    let repair_jobs = chains.iter().cloned().map(Job::RepairGaps);
+    import_jobs.chain(repair_jobs).collect()
 }
 
-fn init_jobs(chains: &[Chain], cmd: Command) -> Vec<Job> {
-    match cmd {
-        Command::Run => {
-            let import_jobs = init_jobs(chains, Command::Import);
-            let calculate_jobs = init_jobs(chains, Command::Calculate);
-            let remove_jobs = init_jobs(chains, Command::Remove);
-            import_jobs
-                .into_iter()
-                .chain(calculate_jobs.into_iter())
-                .chain(remove_jobs.into_iter())
-                .collect()
-        }
-        Command::Import => chains.iter().cloned().map(Job::Import).collect(),
-        Command::Calculate => vec![Job::Calculate(chains.to_vec())],
-        Command::Remove => vec![Job::Remove(chains.to_vec())],
-    }
+async fn make_job_runner(rpc_config: &RpcConfig) -> Result<JobRunner> {
+    let db = Arc::new(BlockingDb::new(JsonDb));
+    JobRunner::new(
+        db,
+        HashMap::new(),
+        rpc_config.import.clone(),
+        rpc_config.calculate.clone(),
+    )
+    .await
 }
 
-fn make_job_runner() -> Result<JobRunner> {
-    Ok(JobRunner {
-        db: Arc::new(JsonDb),
-        clients: HashMap::new(),
-    })
-}
 async fn make_job_runner_with_clients(
     chains: &[Chain],
     rpc_config: &RpcConfig,
 ) -> Result<JobRunner> {
     let clients = make_all_clients(chains, rpc_config).await?;
 
-    Ok(JobRunner {
-        db: Arc::new(JsonDb),
+    let db = Arc::new(BlockingDb::new(JsonDb));
+    JobRunner::new(
+        db,
         clients,
-    })
-}
-
-async fn make_all_clients(
-    chains: &[Chain],
-    rpc_config: &RpcConfig,
-) -> Result<HashMap<Chain, Box<dyn Client>>> {
-    let mut client_futures = FuturesUnordered::new();
-
-    for chain in chains {
-        let rpc_url = get_rpc_url(chain, rpc_config).to_string();
-        let client_future = task::spawn(make_client(*chain, rpc_url));
-        let client_future = client_future.map(move |client| (*chain, client));
-        client_futures.push(client_future);
-    }
-
-    let mut clients = HashMap::new();
-
-    while let Some((chain, client)) = client_futures.next().await {
-        let client = client?;
-        match client {
-            Ok(Some(client)) => {
-                clients.insert(chain, client);
-            }
-            Ok(None) => { /* pass */ }
-            Err(e) => {
-                error!("{}", e);
-            }
-        }
-    }
-
-    Ok(clients)
-}
-
-async fn make_client(chain: Chain, rpc_url: String) -> Result<Option<Box<dyn Client>>> {
-    info!("creating client for {} at {}", chain, rpc_url);
-
-    let client: Option<Box<dyn Client>> = match chain.chain_type() {
-        ChainType::Algorand => Some(Box::new(AlgorandClient::new(&rpc_url)?)),
-        ChainType::Esplora => Some(Box::new(EsploraClient::new(&rpc_url)?)),
-        ChainType::MultiversX => Some(Box::new(MultiversXClient::new(&rpc_url)?)),
-        ChainType::Ethers => Some(Box::new(EthersClient::new(chain, &rpc_url)?)),
-        ChainType::Hedera => Some(Box::new(HederaClient::new(&rpc_url)?)),
-        ChainType::Near => Some(Box::new(NearClient::new(&rpc_url)?)),
-        ChainType::Pivx => Some(Box::new(PivxClient::new(&rpc_url)?)),
-        ChainType::Solana => Some(Box::new(SolanaClient::new(&rpc_url)?)),
-        ChainType::Stellar => Some(Box::new(StellarClient::new(&rpc_url)?)),
-        ChainType::Tendermint => Some(Box::new(TendermintClient::new(chain, &rpc_url)?)),
-        ChainType::Substrate => Some(Box::new(SubstrateClient::new(chain, &rpc_url).await?)),
-    };
-
-    if let Some(ref client) = client {
-        let version = retry_if_err(chain, || client.client_version())
-            .await
-            .context(format!("error getting client version for {}", chain))?;
-        info!("node version for chain {}: {}", chain, version);
-    }
-
-    Ok(client)
-}
-
-fn get_rpc_url<'a>(chain: &Chain, rpc_config: &'a RpcConfig) -> &'a str {
-    if let Some(url) = rpc_config.chains.get(chain) {
-        url
-    } else {
-        todo!()
-    }
+        rpc_config.import.clone(),
+        rpc_config.calculate.clone(),
+    )
+    .await
 }
@@ -1,25 +1,32 @@
 use crate::delay;
-use crate::Chain;
 use log::debug;
+use realtps_common::chain::Chain;
 use std::time::Instant;
 use tokio::time::{self, Duration};
 
 pub struct PaceSetter {
     chain: Chain,
+    /// The pace to request blocks at, in ms.
+    block_pace: u64,
     last_time: Instant,
 }
 
 impl PaceSetter {
     pub fn new(chain: Chain) -> Self {
+        PaceSetter::with_block_pace(chain, delay::block_pace(chain))
+    }
+
+    pub fn with_block_pace(chain: Chain, block_pace: u64) -> Self {
         PaceSetter {
             chain,
+            block_pace,
             last_time: Instant::now(),
         }
     }
 
     pub async fn wait(&mut self) -> &mut Self {
         let work_duration = Instant::now().duration_since(self.last_time);
-        let block_pace = Duration::from_millis(delay::block_pace(self.chain));
+        let block_pace = Duration::from_millis(self.block_pace);
 
         if let Some(to_delay) = block_pace.checked_sub(work_duration) {
             debug!(
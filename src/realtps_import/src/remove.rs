@@ -1,5 +1,6 @@
 #![allow(unused)]
 
+use crate::async_db::{AsyncDb, BlockWalker};
 use crate::client::Client;
 use crate::delay;
 use crate::helpers::*;
@@ -8,43 +9,39 @@ use log::{debug, info, warn};
 use realtps_common::{
     chain::Chain,
     db::{Block, Db},
+    window::Window,
 };
 use std::sync::Arc;
 
-pub async fn remove_old_data_for_chain(chain: Chain, db: Arc<dyn Db>) -> Result<()> {
+/// Removes blocks older than the retention window, counting back from the
+/// highest known block.
+pub async fn remove_old_data_for_chain(
+    chain: Chain,
+    db: Arc<dyn AsyncDb>,
+    retention: Window,
+) -> Result<()> {
     let highest_block_number = load_highest_known_block_number(chain, &db).await?;
     let highest_block_number =
         highest_block_number.ok_or_else(|| anyhow!("no data for chain {}", chain))?;
 
-    let load_block = |number| load_block(chain, &db, number);
+    let mut blocks = BlockWalker::new(db.as_ref(), chain, highest_block_number);
 
-    let latest_timestamp = load_block(highest_block_number)
-        .await?
-        .expect("first block")
-        .timestamp;
+    let mut current_block = blocks.next().await?.expect("first block");
+    let latest_timestamp = current_block.timestamp;
 
-    let seconds_per_week = 60 * 60 * 24 * 7;
-    let min_timestamp = latest_timestamp
-        .checked_sub(seconds_per_week)
-        .expect("underflow");
+    // Local devnets may not be that old, or even have real timestamps
+    let min_timestamp = latest_timestamp.saturating_sub(retention.seconds());
 
-    let mut current_block = load_block(highest_block_number).await?.expect("firt_block");
     let mut to_remove_blocks = vec![];
     let mut is_old_block = false;
+    let mut lowest_kept_block_number = None;
 
     loop {
         if is_old_block {
             to_remove_blocks.push(current_block.block_number);
         }
 
-        let prev_block_number = current_block.prev_block_number;
-
-        if prev_block_number.is_none() {
-            break;
-        }
-        let prev_block_number = prev_block_number.unwrap();
-
-        let prev_block = load_block(prev_block_number).await?;
+        let prev_block = blocks.next().await?;
 
         if prev_block.is_none() {
             break;
@@ -54,6 +51,7 @@ pub async fn remove_old_data_for_chain(chain: Chain, db: Arc<dyn Db>) -> Result<
 
         if !is_old_block && prev_block.timestamp < min_timestamp {
             is_old_block = true;
+            lowest_kept_block_number = Some(current_block.block_number);
         }
 
         current_block = prev_block;
@@ -68,7 +66,17 @@ pub async fn remove_old_data_for_chain(chain: Chain, db: Arc<dyn Db>) -> Result<
 
         to_remove_blocks.reverse();
 
-        remove_blocks(chain, &db, to_remove_blocks).await?;
+        db.remove_blocks(chain, to_remove_blocks).await?;
+
+        if let Some(lowest_kept_block_number) = lowest_kept_block_number {
+            store_lowest_known_block_number(chain, &db, lowest_kept_block_number).await?;
+            db.remove_quarantined_blocks_below(chain, lowest_kept_block_number)
+                .await?;
+            db.remove_orphan_blocks_below(chain, lowest_kept_block_number)
+                .await?;
+            db.remove_reorgs_below(chain, lowest_kept_block_number)
+                .await?;
+        }
     } else {
         info!("no old data in chain {}", chain);
     }
@@ -0,0 +1,305 @@
+use crate::async_db::{AsyncDb, BlockWalker};
+use crate::client::Client;
+use crate::helpers::*;
+use crate::pace_setter::PaceSetter;
+use crate::shutdown::Shutdown;
+use anyhow::{anyhow, bail, Result};
+use log::{debug, info, warn};
+use realtps_common::{chain::Chain, db::BlockRef, window::Window};
+use std::sync::Arc;
+
+/// Walks the stored chain down from the highest known block through the
+/// retention window, fetching any block missing from the chain of parent
+/// links above the lowest known block. Without a lowest known block, a
+/// missing block anywhere in the retention window is a gap.
+///
+/// The walk stops early at the block the last complete repair started from,
+/// since the chain below it was already checked. Blocks only ever join the
+/// stored chain linked to it, so a gap can't open up below it again, except
+/// by a reorg replacing that block, when its hash no longer matches.
+///
+/// When shutdown is requested the walk stops after the block in progress,
+/// without moving the cursor, so the next repair walks the rest again.
+///
+/// `calculate_for_chain` stops at the first missing block, so without this a
+/// hole left by an interrupted import shortens the window it measures.
+pub async fn repair_gaps(
+    chain: Chain,
+    client: &dyn Client,
+    db: &Arc<dyn AsyncDb>,
+    block_pace: u64,
+    retention: Window,
+    shutdown: &Shutdown,
+) -> Result<()> {
+    info!("beginning gap repair for chain {}", chain);
+
+    let highest_block_number = load_highest_known_block_number(chain, db).await?;
+    let highest_block_number =
+        highest_block_number.ok_or_else(|| anyhow!("no data for chain {}", chain))?;
+    let lowest_block_number = load_lowest_known_block_number(chain, db).await?;
+    let repair_cursor = db.load_repair_cursor(chain).await?;
+
+    let mut blocks = BlockWalker::new(db.as_ref(), chain, highest_block_number);
+    let mut block = blocks
+        .next()
+        .await?
+        .ok_or_else(|| anyhow!("highest known block missing for chain {}", chain))?;
+    let highest_block = BlockRef::from(&block);
+
+    let min_timestamp = block.timestamp.saturating_sub(retention.seconds());
+
+    let mut pace = PaceSetter::with_block_pace(chain, block_pace);
+    let mut num_repaired_blocks: u64 = 0;
+
+    loop {
+        if block.timestamp < min_timestamp || Some(block.block_number) <= lowest_block_number {
+            break;
+        }
+
+        if shutdown.is_requested() {
+            info!(
+                "stopping gap repair of chain {} for shutdown at block {}",
+                chain, block.block_number
+            );
+            return Ok(());
+        }
+
+        if let Some(repair_cursor) = &repair_cursor {
+            if block.block_number == repair_cursor.block_number && block.hash == repair_cursor.hash
+            {
+                debug!(
+                    "reached block {} checked by the last repair for chain {}",
+                    block.block_number, chain
+                );
+                break;
+            }
+        }
+
+        let prev_block_number = match block.prev_block_number {
+            Some(prev_block_number) => prev_block_number,
+            None => break,
+        };
+
+        // The walker follows the same links, so yields the stored block at
+        // `prev_block_number`, if there is one
+        let prev_block = match blocks.next().await? {
+            Some(prev_block) if prev_block.hash == block.parent_hash => prev_block,
+            prev_block => {
+                if let Some(prev_block) = prev_block {
+                    warn!(
+                        "block {} for chain {} is from another fork; stored hash: {}; expected hash: {}",
+                        prev_block_number, chain, prev_block.hash, block.parent_hash
+                    );
+                    db.store_orphan_block(prev_block).await?;
+                } else {
+                    debug!(
+                        "found gap at block {} for chain {}",
+                        prev_block_number, chain
+                    );
+                }
+
+                let prev_block = fetch_block(chain, client, prev_block_number).await?;
+
+                if prev_block.hash != block.parent_hash {
+                    bail!(
+                        "repaired block {} for chain {} doesn't fit the hash chain; expected hash: {}; fetched hash: {}",
+                        prev_block_number,
+                        chain,
+                        block.parent_hash,
+                        prev_block.hash
+                    );
+                }
+
+                db.store_block(prev_block.clone()).await?;
+                num_repaired_blocks += 1;
+
+                // The walk stopped at the gap, or went down the other fork,
+                // so walk on below the repaired block
+                if let Some(next_block_number) = prev_block.prev_block_number {
+                    blocks = BlockWalker::new(db.as_ref(), chain, next_block_number);
+                }
+
+                pace.wait().await;
+
+                prev_block
+            }
+        };
+
+        block = prev_block;
+    }
+
+    db.store_repair_cursor(chain, highest_block).await?;
+
+    if num_repaired_blocks > 0 {
+        info!(
+            "repaired {} missing blocks for chain {}",
+            num_repaired_blocks, chain
+        );
+    } else {
+        info!("no gaps in chain {}", chain);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_repair {
+    use super::repair_gaps;
+    use crate::async_db::test_async_db::{block, MemoryDb};
+    use crate::async_db::AsyncDb;
+    use crate::client::test_client::FakeClient;
+    use crate::shutdown::Shutdown;
+    use anyhow::Result;
+    use realtps_common::{chain::Chain, window::Window};
+    use std::sync::Arc;
+
+    const CHAIN: Chain = Chain::Ethereum;
+    const RETENTION: Window = Window::from_seconds(1000);
+
+    /// Stores blocks numbered and timestamped from `first_block_number` to
+    /// `highest_block_number`.
+    fn stored_db(first_block_number: u64, highest_block_number: u64) -> Arc<MemoryDb> {
+        let timestamps: Vec<u64> = (first_block_number..=highest_block_number).collect();
+        Arc::new(MemoryDb::with_blocks(
+            CHAIN,
+            first_block_number,
+            &timestamps,
+        ))
+    }
+
+    async fn stored(db: &MemoryDb, block_number: u64) -> Result<bool> {
+        Ok(db.load_block(CHAIN, block_number).await?.is_some())
+    }
+
+    #[tokio::test]
+    async fn gap_repaired() -> Result<()> {
+        let db = stored_db(0, 20);
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        db.store_lowest_block_number(CHAIN, 0).await?;
+        db.remove_blocks(CHAIN, vec![12, 13]).await?;
+        let client = FakeClient::new(CHAIN, 20, vec![]);
+
+        repair_gaps(
+            CHAIN,
+            &client,
+            &async_db,
+            0,
+            RETENTION,
+            &Shutdown::default(),
+        )
+        .await?;
+
+        assert!(stored(&db, 12).await? && stored(&db, 13).await?);
+        assert_eq!(*client.num_blocks_fetched.lock().expect("poisoned"), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fork_block_replaced() -> Result<()> {
+        let db = stored_db(0, 20);
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        db.store_lowest_block_number(CHAIN, 0).await?;
+        db.put_block(block(CHAIN, 15, 15, 1, "x"));
+        let client = FakeClient::new(CHAIN, 20, vec![]);
+
+        repair_gaps(
+            CHAIN,
+            &client,
+            &async_db,
+            0,
+            RETENTION,
+            &Shutdown::default(),
+        )
+        .await?;
+
+        let repaired_block = db.load_block(CHAIN, 15).await?.expect("block");
+        assert_eq!(repaired_block.hash, "15");
+        assert_eq!(*client.num_blocks_fetched.lock().expect("poisoned"), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gap_repaired_without_lowest_block() -> Result<()> {
+        let db = stored_db(10, 20);
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        db.remove_blocks(CHAIN, vec![15]).await?;
+        let client = FakeClient::new(CHAIN, 20, vec![]);
+
+        // Blocks before 12 are out of retention
+        repair_gaps(
+            CHAIN,
+            &client,
+            &async_db,
+            0,
+            Window::from_seconds(8),
+            &Shutdown::default(),
+        )
+        .await?;
+
+        assert!(stored(&db, 15).await?);
+        assert!(!stored(&db, 9).await?);
+        assert_eq!(*client.num_blocks_fetched.lock().expect("poisoned"), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn repair_stops_at_cursor() -> Result<()> {
+        let db = stored_db(0, 100);
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        db.store_lowest_block_number(CHAIN, 0).await?;
+        let client = FakeClient::new(CHAIN, 110, vec![]);
+
+        repair_gaps(
+            CHAIN,
+            &client,
+            &async_db,
+            0,
+            RETENTION,
+            &Shutdown::default(),
+        )
+        .await?;
+        let cursor = db.load_repair_cursor(CHAIN).await?.expect("cursor");
+        assert_eq!(cursor.block_number, 100);
+
+        for block_number in 101..=110 {
+            db.put_block(block(CHAIN, block_number, block_number, 1, ""));
+        }
+        db.set_highest_block_number(CHAIN, 110);
+        db.remove_blocks(CHAIN, vec![105]).await?;
+        *db.num_blocks_loaded.lock().expect("poisoned") = 0;
+
+        repair_gaps(
+            CHAIN,
+            &client,
+            &async_db,
+            0,
+            RETENTION,
+            &Shutdown::default(),
+        )
+        .await?;
+
+        assert!(stored(&db, 105).await?);
+        assert!(*db.num_blocks_loaded.lock().expect("poisoned") < 50);
+        let cursor = db.load_repair_cursor(CHAIN).await?.expect("cursor");
+        assert_eq!(cursor.block_number, 110);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn repair_stops_for_shutdown() -> Result<()> {
+        let db = stored_db(0, 20);
+        let async_db: Arc<dyn AsyncDb> = db.clone();
+        db.store_lowest_block_number(CHAIN, 0).await?;
+        db.remove_blocks(CHAIN, vec![15, 12]).await?;
+        let shutdown = Shutdown::default();
+        let mut client = FakeClient::new(CHAIN, 20, vec![]);
+        client.shutdown_at = Some((15, shutdown.clone()));
+
+        repair_gaps(CHAIN, &client, &async_db, 0, RETENTION, &shutdown).await?;
+
+        assert!(stored(&db, 15).await?);
+        assert!(!stored(&db, 12).await?);
+        assert!(db.load_repair_cursor(CHAIN).await?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use crate::async_db::AsyncDb;
+use crate::client::Client;
+use crate::delay::retry_if_err;
+use crate::helpers::*;
+use anyhow::Result;
+use chrono::{DateTime, TimeZone, Utc};
+use futures::future::FutureExt;
+use futures::stream::{FuturesUnordered, StreamExt};
+use realtps_common::{chain::Chain, db::JobState, window::Window};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+struct ChainStatus {
+    chain: Chain,
+    highest_block_number: Option<u64>,
+    highest_block_time: Option<DateTime<Utc>>,
+    /// The finalized head for chains whose client knows about finality,
+    /// otherwise the live head.
+    head_block_number: Option<u64>,
+    num_blocks: Option<u64>,
+    tps: Option<f64>,
+    calculated: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+}
+
+/// Prints a summary of each chain's import health to stdout,
+/// with the TPS over `window`.
+///
+/// Lag is measured from the finalized head for chains whose client knows
+/// about finality, since blocks past it can still be reorged out.
+/// Chains without a client are still summarized from the db,
+/// just without the head.
+pub async fn print_status(
+    chains: &[Chain],
+    clients: &HashMap<Chain, Box<dyn Client>>,
+    db: &Arc<dyn AsyncDb>,
+    window: Window,
+) -> Result<()> {
+    let job_statuses = db.load_job_statuses().await?;
+
+    let mut head_futures: FuturesUnordered<_> = chains
+        .iter()
+        .filter_map(|chain| {
+            let client = clients.get(chain)?;
+            let head_future = fetch_head_block_number(*chain, client.as_ref());
+            Some(head_future.map(move |head| (*chain, head)))
+        })
+        .collect();
+
+    let mut heads = HashMap::new();
+    let mut head_errors = HashMap::new();
+    while let Some((chain, head)) = head_futures.next().await {
+        match head {
+            Ok(head) => {
+                heads.insert(chain, head);
+            }
+            Err(e) => {
+                head_errors.insert(chain, format!("{:#}", e));
+            }
+        }
+    }
+
+    let mut statuses = vec![];
+    for chain in chains {
+        let chain = *chain;
+
+        let highest_block_number = load_highest_known_block_number(chain, db).await?;
+        let highest_block_time = match highest_block_number {
+            Some(number) => db
+                .load_block(chain, number)
+                .await?
+                .and_then(|block| i64::try_from(block.timestamp).ok())
+                .map(|timestamp| Utc.timestamp(timestamp, 0)),
+            None => None,
+        };
+        let calculation_log = db.load_calculation_log(chain, window).await?;
+        let tps = db
+            .load_window_calcs(chain, window)
+            .await?
+            .map(|calcs| calcs.tps);
+
+        // Prefer errors from jobs that are still failing, then a failure to
+        // reach the node just now.
+        let failing_job_error = job_statuses
+            .iter()
+            .filter(|status| status.chain == chain)
+            .filter(|status| status.state == JobState::Failing)
+            .find_map(|status| {
+                let last_error = status.last_error.as_ref()?;
+                Some(format!("{}: {}", status.job, last_error))
+            });
+        let last_error = failing_job_error.or_else(|| head_errors.remove(&chain));
+
+        statuses.push(ChainStatus {
+            chain,
+            highest_block_number,
+            highest_block_time,
+            head_block_number: heads.get(&chain).cloned(),
+            num_blocks: calculation_log.as_ref().map(|log| log.num_blocks),
+            tps,
+            calculated: calculation_log.as_ref().map(|log| log.calculating_end),
+            last_error,
+        });
+    }
+
+    print_table(&statuses);
+
+    Ok(())
+}
+
+async fn fetch_head_block_number(chain: Chain, client: &dyn Client) -> Result<u64> {
+    let finalized_head_block_number =
+        retry_if_err(chain, || Box::pin(client.get_finalized_block_number())).await?;
+    match finalized_head_block_number {
+        Some(block_number) => Ok(block_number),
+        None => fetch_live_head_block_number(chain, client).await,
+    }
+}
+
+fn print_table(statuses: &[ChainStatus]) {
+    let fmt_time = |time: Option<DateTime<Utc>>| {
+        time.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "-".to_string())
+    };
+    let fmt_opt = |value: Option<u64>| {
+        value
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| "-".to_string())
+    };
+
+    println!(
+        "{:<12} {:>12} {:<19} {:>12} {:>8} {:>8} {:>10} {:<19} last error",
+        "chain", "highest", "highest time", "head", "lag", "blocks", "tps", "calculated"
+    );
+    for status in statuses {
+        let lag = match (status.head_block_number, status.highest_block_number) {
+            (Some(head), Some(highest)) => Some(head.saturating_sub(highest)),
+            _ => None,
+        };
+        let tps = status
+            .tps
+            .map(|tps| format!("{:.2}", tps))
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:<12} {:>12} {:<19} {:>12} {:>8} {:>8} {:>10} {:<19} {}",
+            status.chain.to_string(),
+            fmt_opt(status.highest_block_number),
+            fmt_time(status.highest_block_time),
+            fmt_opt(status.head_block_number),
+            fmt_opt(lag),
+            fmt_opt(status.num_blocks),
+            tps,
+            fmt_time(status.calculated),
+            status.last_error.as_deref().unwrap_or("-"),
+        );
+    }
+}
@@ -1,21 +1,28 @@
 #[macro_use]
 extern crate rocket;
 
-use chrono::Duration;
+use chrono::{Duration, Utc};
 use realtps_common::{
     chain::Chain,
-    db::{CalculationLog, Db, JsonDb},
+    db::{CalculationLog, Db, JobStatus, JsonDb, PeakTps, Reorg},
+    window::Window,
 };
 use rocket::fs::{relative, FileServer};
 use rocket_dyn_templates::Template;
 use serde::{Deserialize, Serialize};
 
+/// Below this much of the window, a chain's TPS is flagged as low coverage.
+const LOW_COVERAGE_PERCENT: f64 = 90.0;
+
 #[derive(Serialize, Deserialize, Debug)]
 struct EmptyContext {}
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Context {
     rows: Vec<Row>,
+    window: Window,
+    windows: Vec<Window>,
+    peak_intervals: Vec<Window>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -25,12 +32,31 @@ struct Row {
     note: Option<String>,
     tps: f64,
     tps_str: String,
+    /// The TPS of system, failed and inner transactions, which aren't in
+    /// `tps`, for the cell's tooltip. Empty if there are none.
+    tps_title: String,
+    /// Millions of gas per second, for EVM chains.
+    mgas_str: Option<String>,
+    /// Operations per second, for Stellar.
+    ops_str: Option<String>,
+    /// One per peak interval, in the same order.
+    peaks: Vec<PeakCell>,
     is_data_too_old: bool,
+    is_low_coverage: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PeakCell {
+    tps_str: String,
+    /// When the peak was, for the cell's tooltip.
+    span: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct LogContext {
     log_list: Vec<Log>,
+    window: Window,
+    windows: Vec<Window>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -40,19 +66,41 @@ struct Log {
     log_details: CalculationLog,
 }
 
-#[get("/")]
-fn index() -> Template {
+#[derive(Serialize, Deserialize, Debug)]
+struct ReorgContext {
+    chain_list: Vec<ChainReorgs>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ChainReorgs {
+    chain_id: Chain,
+    chain_name: String,
+    reorgs_past_week: usize,
+    max_depth_past_week: u64,
+    reorgs: Vec<Reorg>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JobsContext {
+    job_list: Vec<JobStatus>,
+}
+
+#[get("/?<window>")]
+fn index(window: Option<&str>) -> Template {
     let mut list = Vec::new();
     let db = JsonDb;
+    let window = parse_window(window);
 
     for chain in Chain::all_chains() {
-        if let Some(tps) = db
-            .load_tps(chain)
+        if let Some(calcs) = db
+            .load_window_calcs(chain, window)
             .unwrap_or_else(|_| panic!("No tps data for chain {}", &chain))
         {
+            let tps = calcs.tps;
             let mut is_data_too_old = false;
+            let mut is_low_coverage = false;
             if let Some(log_details) = db
-                .load_calculation_log(chain)
+                .load_calculation_log(chain, window)
                 .unwrap_or_else(|_| panic!("No calculation log for chain {}", &chain))
             {
                 if log_details.calculating_start - log_details.newest_block_timestamp
@@ -60,12 +108,36 @@ fn index() -> Template {
                 {
                     is_data_too_old = true;
                 }
+                if let Some(coverage_percent) = log_details.coverage_percent {
+                    if coverage_percent < LOW_COVERAGE_PERCENT {
+                        is_low_coverage = true;
+                    }
+                }
             }
 
             let note = chain_note(chain).map(ToString::to_string);
             let chain_id = chain;
             let chain_name = chain.description().to_string();
             let tps_str = format!("{:.2}", tps);
+            let tps_title = [
+                (calcs.system_tps, "system"),
+                (calcs.failed_tps, "failed"),
+                (calcs.inner_tps, "inner"),
+            ]
+            .into_iter()
+            .filter(|(other_tps, _)| *other_tps > 0.0)
+            .map(|(other_tps, kind)| format!("plus {:.2} {} TPS", other_tps, kind))
+            .collect::<Vec<_>>()
+            .join(", ");
+            let mgas_str = calcs
+                .gas_per_second
+                .map(|gas| format!("{:.2}", gas / 1_000_000.0));
+            let ops_str = calcs.ops_per_second.map(|ops| format!("{:.2}", ops));
+
+            let peaks = Window::peak_intervals()
+                .into_iter()
+                .map(|interval| peak_cell(&calcs.peaks, interval))
+                .collect();
 
             list.push(Row {
                 chain_id,
@@ -73,23 +145,34 @@ fn index() -> Template {
                 note,
                 tps,
                 tps_str,
+                tps_title,
+                mgas_str,
+                ops_str,
+                peaks,
                 is_data_too_old,
+                is_low_coverage,
             });
         }
     }
 
-    let context = Context { rows: list };
+    let context = Context {
+        rows: list,
+        window,
+        windows: calculated_windows(&db),
+        peak_intervals: Window::peak_intervals(),
+    };
     Template::render("index", &context)
 }
 
-#[get("/log")]
-fn log() -> Template {
+#[get("/log?<window>")]
+fn log(window: Option<&str>) -> Template {
     let mut list = Vec::new();
     let db = JsonDb;
+    let window = parse_window(window);
 
     for chain in Chain::all_chains() {
         if let Some(log_details) = db
-            .load_calculation_log(chain)
+            .load_calculation_log(chain, window)
             .unwrap_or_else(|_| panic!("No calculation log for chain {}", &chain))
         {
             let chain_id = chain;
@@ -103,26 +186,132 @@ fn log() -> Template {
         }
     }
 
-    let context = LogContext { log_list: list };
+    let context = LogContext {
+        log_list: list,
+        window,
+        windows: calculated_windows(&db),
+    };
     Template::render("log", &context)
 }
 
+#[get("/reorgs")]
+fn reorgs() -> Template {
+    let mut list = Vec::new();
+    let db = JsonDb;
+    let one_week_ago = Utc::now() - Duration::weeks(1);
+
+    for chain in Chain::all_chains() {
+        let mut reorgs = db
+            .load_reorgs(chain)
+            .unwrap_or_else(|_| panic!("No reorg history for chain {}", &chain));
+
+        if reorgs.is_empty() {
+            continue;
+        }
+
+        // Newest first
+        reorgs.reverse();
+
+        let reorgs_past_week = reorgs
+            .iter()
+            .filter(|reorg| reorg.time > one_week_ago)
+            .count();
+        let max_depth_past_week = reorgs
+            .iter()
+            .filter(|reorg| reorg.time > one_week_ago)
+            .map(|reorg| reorg.depth)
+            .max()
+            .unwrap_or(0);
+
+        let chain_id = chain;
+        let chain_name = chain.description().to_string();
+
+        list.push(ChainReorgs {
+            chain_id,
+            chain_name,
+            reorgs_past_week,
+            max_depth_past_week,
+            reorgs,
+        });
+    }
+
+    let context = ReorgContext { chain_list: list };
+    Template::render("reorgs", &context)
+}
+
+#[get("/jobs")]
+fn jobs() -> Template {
+    let db = JsonDb;
+    let job_list = db
+        .load_job_statuses()
+        .unwrap_or_else(|e| panic!("No job statuses: {}", e));
+
+    let context = JobsContext { job_list };
+    Template::render("jobs", &context)
+}
+
 #[get("/about")]
 fn about() -> Template {
     Template::render("about", EmptyContext {})
 }
 
+fn peak_cell(peaks: &[PeakTps], interval: Window) -> PeakCell {
+    match peaks.iter().find(|peak| peak.interval == interval) {
+        Some(peak) => PeakCell {
+            tps_str: format!("{:.2}", peak.tps),
+            span: format!(
+                "{} to {}",
+                peak.start.format("%Y-%m-%d %H:%M"),
+                peak.end.format("%Y-%m-%d %H:%M UTC")
+            ),
+        },
+        None => PeakCell {
+            tps_str: "-".to_string(),
+            span: String::new(),
+        },
+    }
+}
+
+/// The windows any chain has calculations for, shortest first, so that the
+/// nav only links windows the importer is configured to calculate.
+fn calculated_windows(db: &JsonDb) -> Vec<Window> {
+    let mut windows: Vec<Window> = Chain::all_chains()
+        .into_iter()
+        .flat_map(|chain| {
+            db.load_calculated_windows(chain)
+                .unwrap_or_else(|e| panic!("No windows for chain {}: {}", &chain, e))
+        })
+        .collect();
+    windows.sort();
+    windows.dedup();
+    windows
+}
+
+/// The window asked for in the URL, or the default for a missing or
+/// unknown window.
+fn parse_window(window: Option<&str>) -> Window {
+    window
+        .and_then(|window| Window::try_from(window).ok())
+        .unwrap_or_default()
+}
+
 #[launch]
 fn rocket() -> _ {
     rocket::build()
-        .mount("/", routes![index, about, log])
+        .mount("/", routes![index, about, log, reorgs, jobs])
         .mount("/static", FileServer::from(relative!("static")))
         .attach(Template::fairing())
 }
 
 fn chain_note(chain: Chain) -> Option<&'static str> {
     match chain {
+        Chain::Algorand => Some("algorand"),
         Chain::Solana => Some("solana"),
+        Chain::Arbitrum | Chain::Optimism => Some("rollups"),
+        Chain::CosmosHub | Chain::Osmosis | Chain::SecretNetwork | Chain::Terra => Some("cosmos"),
+        Chain::Acala | Chain::Bifrost | Chain::Karura | Chain::Kusama | Chain::Polkadot => {
+            Some("substrate")
+        }
         _ => None,
     }
 }